@@ -0,0 +1,79 @@
+/// Operational views of the proxy, not part of the Ollama API
+use anyhow::Context;
+use axum::extract::{Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Json, Response};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::costs::{self, GroupBy};
+use crate::error::ApiError;
+use crate::structs::app::AppState;
+
+/// The config file as it is now, an Ollama style error if it doesn't load
+fn load_config(state: &AppState) -> Result<Config, ApiError> {
+    Config::from_file(&state.config_path)
+        .context("Failed to load config")
+        .map_err(ApiError::from)
+}
+
+/// `GET /admin/keys`, state of the API keys of every model, masked
+pub async fn keys(State(state): State<Arc<AppState>>) -> Result<Json<Value>, ApiError> {
+    let config = load_config(&state)?;
+
+    let models = config
+        .models()
+        .iter()
+        .map(|(alias, model)| (alias.as_str(), state.keys.report_model(model)))
+        .collect::<BTreeMap<_, _>>();
+
+    Ok(Json(json!({ "models": models })))
+}
+
+/// `GET /admin/usage`, current consumption of every client
+pub async fn usage(State(state): State<Arc<AppState>>) -> Json<Value> {
+    Json(json!({ "clients": state.limits.consumption() }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CostsQuery {
+    #[serde(default)]
+    by: GroupBy,
+    /// `csv` for a CSV export, JSON otherwise
+    format: Option<String>,
+}
+
+/// `GET /admin/costs?by=model|client|day&format=csv`, cost of the requests
+pub async fn costs(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<CostsQuery>,
+) -> Result<Response, ApiError> {
+    let config = load_config(&state)?;
+
+    let records = state.costs.records(&config.accounting.costs_file);
+    let rows = costs::report(&records, query.by);
+    if query.format.as_deref() == Some("csv") {
+        let csv = costs::to_csv(&rows, query.by);
+        return Ok(([(CONTENT_TYPE, "text/csv; charset=utf-8")], csv).into_response());
+    }
+
+    Ok(Json(json!({ "costs": rows })).into_response())
+}
+
+/// `GET /admin/cache`, hits, misses and entries of the response cache
+pub async fn cache(State(state): State<Arc<AppState>>) -> Result<Json<Value>, ApiError> {
+    let config = load_config(&state)?;
+
+    Ok(Json(state.cache.stats(&config.cache)))
+}
+
+/// `DELETE /admin/cache`, drop every cached response
+pub async fn purge_cache(State(state): State<Arc<AppState>>) -> Result<Json<Value>, ApiError> {
+    let config = load_config(&state)?;
+
+    let purged = state.cache.purge(&config.cache);
+    Ok(Json(json!({ "purged": purged })))
+}
@@ -1,30 +1,86 @@
 use std::sync::Arc;
 
 use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
 use serde_json::json;
 
+use crate::admin;
+use crate::auth::authenticate;
+use crate::config::Config;
 use crate::ollama::chat_handler as chat;
+use crate::ollama::embed_handler as embed;
+use crate::ollama::embeddings_handler as embeddings;
 use crate::ollama::generate_handler as generate;
 use crate::ollama::models;
+use crate::ollama::openai_embeddings_handler as openai_embeddings;
+use crate::ollama::show_handler as show;
 
 use crate::structs::app::AppState;
+use crate::structs::config::CorsConfig;
+use axum::http::HeaderValue;
 use axum::{
+    middleware,
     response::Json,
     routing::{get, post},
     Router,
 };
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 
 pub async fn create_app(app_state: Arc<AppState>) -> Router {
-    Router::new()
+    let cors = Config::from_file(&app_state.config_path)
+        .map(|config| config.server.cors)
+        .unwrap_or_default();
+
+    let router = Router::new()
         .route("/api/chat", post(chat))
         .route("/api/tags", get(models)) //  或 /api/models
         .route("/api/ping", get(ping))
+        .route("/api/show", post(show))
         .route("/api/generate", post(generate))
-        .with_state(app_state)
-        .layer(CorsLayer::new().allow_origin(Any))
+        .route("/api/embed", post(embed))
+        .route("/api/embeddings", post(embeddings))
+        .route("/v1/embeddings", post(openai_embeddings))
+        .route("/admin/keys", get(admin::keys))
+        .route("/admin/usage", get(admin::usage))
+        .route("/admin/costs", get(admin::costs))
+        .route("/admin/cache", get(admin::cache).delete(admin::purge_cache))
+        .route("/metrics", get(metrics))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            authenticate,
+        ))
+        .with_state(app_state);
+    match cors_layer(&cors) {
+        Some(layer) => router.layer(layer),
+        None => router,
+    }
+}
+
+/// `None` when no origin is allowed
+fn cors_layer(cors: &CorsConfig) -> Option<CorsLayer> {
+    if cors.allow_origins.is_empty() {
+        return None;
+    }
+    if cors.allow_origins.iter().any(|origin| origin == "*") {
+        return Some(CorsLayer::new().allow_origin(Any));
+    }
+    let origins = cors
+        .allow_origins
+        .iter()
+        .filter_map(|origin| HeaderValue::from_str(origin).ok())
+        .collect::<Vec<_>>();
+    Some(CorsLayer::new().allow_origin(AllowOrigin::list(origins)))
 }
 
 async fn ping(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
     Json(json!({"model_name": state.model_name.clone()}))
 }
+
+/// Prometheus metrics, outside of the authenticated routes for the scraper
+async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
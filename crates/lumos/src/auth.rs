@@ -0,0 +1,145 @@
+/// Bearer token authentication of the `/api`, `/v1` and `/admin` routes,
+/// enabled when the config file has `[[auth.clients]]`.
+/// Authenticated requests are also checked against the limits of the client.
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::middleware::Next;
+use axum::response::Response;
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::costs::ANONYMOUS;
+use crate::error::ApiError;
+use crate::logging;
+use crate::structs::app::AppState;
+use crate::structs::config::ClientKey;
+
+/// Largest request body read to find the requested model
+const BODY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// The authenticated client, added to the request extensions
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub name: String,
+}
+
+/// Client and model of a request, as far as they are known when it's refused
+#[derive(Default)]
+struct Requester {
+    client: Option<String>,
+    model: Option<String>,
+}
+
+/// Every refused request is logged like the dispatched ones
+pub async fn authenticate(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let mut requester = Requester::default();
+    authorize(&state, request, next, &mut requester)
+        .await
+        .inspect_err(|e| {
+            logging::rejected(
+                requester.client.as_deref().unwrap_or(ANONYMOUS),
+                requester.model.as_deref(),
+                e.status,
+                &e.message,
+            )
+        })
+}
+
+async fn authorize(
+    state: &AppState,
+    request: Request,
+    next: Next,
+    requester: &mut Requester,
+) -> Result<Response, ApiError> {
+    let path = request.uri().path();
+    let admin = path.starts_with("/admin/");
+    if !(admin || path.starts_with("/api/") || path.starts_with("/v1/")) {
+        return Ok(next.run(request).await);
+    }
+
+    let config = Config::from_file(&state.config_path).map_err(|e| {
+        ApiError::new(
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to load config: {}", e),
+        )
+    })?;
+    let auth = match &config.auth {
+        Some(auth) if !auth.clients.is_empty() => auth,
+        _ => return Ok(next.run(request).await),
+    };
+
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| ApiError::unauthorized("missing bearer token"))?;
+    let client = find_client(&auth.clients, token)
+        .ok_or_else(|| ApiError::unauthorized("invalid API key"))?
+        .clone();
+    requester.client = Some(client.name.clone());
+
+    if admin && !client.admin {
+        return Err(ApiError::forbidden(format!(
+            "client {} may not use admin routes",
+            client.name
+        )));
+    }
+
+    // the requested model is in the JSON body of every model route
+    let (parts, body) = request.into_parts();
+    let bytes = to_bytes(body, BODY_LIMIT)
+        .await
+        .map_err(|e| ApiError::new(axum::http::StatusCode::BAD_REQUEST, e.to_string()))?;
+    let model = serde_json::from_slice::<Value>(&bytes)
+        .ok()
+        .and_then(|json| {
+            let model = json["model"].as_str().or(json["name"].as_str())?;
+            Some(config.resolve(model).unwrap_or(model).to_string())
+        });
+    requester.model = model.clone();
+    if let Some(model) = model {
+        if !client.allows(&model) {
+            return Err(ApiError::forbidden(format!(
+                "client {} may not use model {}",
+                client.name, model
+            )));
+        }
+    }
+
+    let guard = state
+        .limits
+        .acquire(&client, &auth.usage_file)
+        .map_err(|limited| ApiError::too_many_requests(limited.message, limited.retry_after))?;
+
+    let mut request = Request::from_parts(parts, Body::from(bytes));
+    request
+        .extensions_mut()
+        .insert(Client { name: client.name });
+
+    // the stream counts against the client until its body is dropped
+    let (parts, body) = next.run(request).await.into_parts();
+    let body = Body::from_stream(body.into_data_stream().map(move |chunk| {
+        let _ = &guard;
+        chunk
+    }));
+    Ok(Response::from_parts(parts, body))
+}
+
+fn find_client<'a>(clients: &'a [ClientKey], token: &str) -> Option<&'a ClientKey> {
+    clients
+        .iter()
+        .find(|client| constant_time_eq(client.key.as_bytes(), token.as_bytes()))
+}
+
+/// Compare keys without leaking the matching prefix through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,624 @@
+/// `lumos config check`, every problem of a config file with its line.
+/// `Config::from_file` stops at the first error and ignores unknown fields,
+/// here the whole file is checked and unknown fields are errors.
+use anyhow::Result;
+use serde::de::{self, DeserializeOwned, Visitor};
+use std::collections::HashMap;
+use std::fmt;
+use toml::Value;
+use toml_edit::{ImDocument, Item, TableLike};
+
+use crate::config::expand_models;
+use crate::interpolate;
+use crate::logging;
+use crate::names;
+use crate::structs::config::{
+    AccountingConfig, AuthConfig, Balance, CacheConfig, ClientKey, CorsConfig, Endpoint,
+    MockScript, Model, ModelOptions, Pricing, ProviderName, RetryPolicy, ServerConfig, Timeouts,
+    TranscriptConfig,
+};
+
+/// Tables of the config file that are not models
+const SECTIONS: &[&str] = &[
+    "server",
+    "auth",
+    "accounting",
+    "transcripts",
+    "cache",
+    "providers",
+    "models",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// 1-based, 0 if the problem is not on a line of the file
+    pub line: usize,
+    pub message: String,
+}
+
+pub fn check_file(path: &str) -> Result<Vec<Diagnostic>> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(check(&contents))
+}
+
+/// Problems of the contents of a config file, sorted by line
+pub fn check(contents: &str) -> Vec<Diagnostic> {
+    let mut checker = Checker::default();
+
+    let document = match ImDocument::parse(contents) {
+        Ok(document) => document,
+        Err(e) => {
+            let line = e.span().map_or(0, |span| line_of(contents, span.start));
+            let message = e.message().trim().replace('\n', ", ");
+            checker.push(Severity::Error, line, message);
+            return checker.diagnostics;
+        }
+    };
+    locate(document.as_table(), "", contents, &mut checker.lines);
+    let mut value = match contents.parse::<Value>() {
+        Ok(value) => value,
+        Err(e) => {
+            checker.error("", e.message().to_string());
+            return checker.diagnostics;
+        }
+    };
+
+    let (secrets, errors) = interpolate::resolve_all(&mut value);
+    logging::register_secrets(secrets.iter().map(String::as_str));
+    for (path, error) in errors {
+        checker.error(&path, error.to_string());
+    }
+
+    let (models, errors) = expand_models(&value);
+    for (path, error) in errors {
+        checker.error(&path, error);
+    }
+    let Value::Table(root) = value else {
+        return checker.diagnostics;
+    };
+
+    // alias and table of every model, flat or declared in `[models]`
+    let mut places = root
+        .iter()
+        .filter(|(name, _)| !SECTIONS.contains(&name.as_str()))
+        .map(|(name, value)| (name.clone(), name.clone(), value))
+        .collect::<Vec<_>>();
+    if let Some(Value::Table(declared)) = root.get("models") {
+        places.extend(
+            declared
+                .iter()
+                .map(|(alias, value)| (alias.clone(), format!("models.{}", alias), value)),
+        );
+    }
+    let aliases = places
+        .iter()
+        .map(|(alias, _, _)| alias.clone())
+        .collect::<Vec<_>>();
+
+    // alias, name and path of every name of the models
+    let mut model_names = Vec::new();
+    for (alias, path, value) in &places {
+        let mut own = vec![
+            (alias.clone(), path.clone()),
+            (names::ollama_name(alias), path.clone()),
+        ];
+        let extra = value.get("aliases").and_then(Value::as_array);
+        for (index, name) in extra.into_iter().flatten().enumerate() {
+            if let Some(name) = name.as_str() {
+                own.push((name.to_string(), format!("{}.aliases[{}]", path, index)));
+            }
+        }
+        let mut seen = Vec::new();
+        for (name, name_path) in own {
+            if !seen.contains(&names::normalize(&name)) {
+                seen.push(names::normalize(&name));
+                model_names.push((alias.clone(), name, name_path));
+            }
+        }
+    }
+
+    for (name, value) in &root {
+        match name.as_str() {
+            "server" => checker.check_server(value, &aliases, root.contains_key("auth")),
+            "auth" => {
+                checker.check_types::<AuthConfig>(name, value.clone());
+                checker.check_auth(name, value, &aliases);
+            }
+            "accounting" => checker.check_section::<AccountingConfig>(name, value),
+            "transcripts" => checker.check_section::<TranscriptConfig>(name, value),
+            "cache" => checker.check_section::<CacheConfig>(name, value),
+            "providers" => {
+                for (provider, value) in value.as_table().into_iter().flatten() {
+                    checker.check_model_fields(
+                        &format!("providers.{}", provider),
+                        value,
+                        &["kind"],
+                    );
+                }
+            }
+            "models" => {
+                for (alias, value) in value.as_table().into_iter().flatten() {
+                    checker.check_model_fields(&format!("models.{}", alias), value, &[]);
+                }
+            }
+            _ => {
+                if checker.check_model_fields(name, value, &[]) {
+                    checker.check_model(name, value, &aliases);
+                }
+            }
+        }
+    }
+    checker.check_duplicates(&model_names);
+    for (alias, model) in &models {
+        let provider = root["models"][alias]["provider"]
+            .as_str()
+            .unwrap_or_default();
+        checker.alias_lines(alias, provider);
+        checker.check_model(alias, model, &aliases);
+    }
+
+    checker
+        .diagnostics
+        .sort_by_key(|diagnostic| diagnostic.line);
+    checker.diagnostics
+}
+
+#[derive(Default)]
+struct Checker {
+    /// Line of each path, e.g. `glm-4-plus.endpoints[1].url`
+    lines: HashMap<String, usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    /// Line of the path, or of its closest parent found in the file
+    fn line(&self, path: &str) -> usize {
+        let mut path = path;
+        loop {
+            if let Some(line) = self.lines.get(path) {
+                return *line;
+            }
+            match path.rfind(['.', '[']) {
+                Some(end) => path = &path[..end],
+                None => return 0,
+            }
+        }
+    }
+
+    fn push(&mut self, severity: Severity, line: usize, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            line,
+            message: logging::redact(&message),
+        });
+    }
+
+    fn error(&mut self, path: &str, message: String) {
+        self.push(Severity::Error, self.line(path), message);
+    }
+
+    fn warning(&mut self, path: &str, message: String) {
+        self.push(Severity::Warning, self.line(path), message);
+    }
+
+    /// Unknown fields and values of the wrong type
+    fn check_section<T: DeserializeOwned>(&mut self, path: &str, value: &Value) {
+        if self.check_fields::<T>(path, value) {
+            self.check_types::<T>(path, value.clone());
+        }
+    }
+
+    /// Report the first value of the wrong type, at the line of its field
+    fn check_types<T: DeserializeOwned>(&mut self, path: &str, value: Value) {
+        if let Err(e) = serde_path_to_error::deserialize::<_, T>(value) {
+            let path = match e.path().to_string().as_str() {
+                "." => path.to_string(),
+                field => format!("{}.{}", path, field),
+            };
+            self.error(&path, format!("{}: {}", path, e.inner().message().trim()));
+        }
+    }
+
+    /// Lines of a model of `[models]` under its alias, taken from
+    /// its table and from the table of its provider
+    fn alias_lines(&mut self, alias: &str, provider: &str) {
+        let mut lines = Vec::new();
+        for (from, to) in [
+            (format!("providers.{}", provider), alias.to_string()),
+            (format!("models.{}", alias), alias.to_string()),
+        ] {
+            for (path, line) in &self.lines {
+                if let Some(rest) = path.strip_prefix(&from) {
+                    if rest.is_empty() || rest.starts_with(['.', '[']) {
+                        lines.push((format!("{}{}", to, rest), *line));
+                    }
+                }
+            }
+        }
+        // a path found in both gets the line of the model
+        self.lines.extend(lines);
+    }
+
+    /// Report the fields `T` doesn't have, false if `value` is not a table
+    fn check_fields<T: DeserializeOwned>(&mut self, path: &str, value: &Value) -> bool {
+        self.check_known(path, value, fields::<T>())
+    }
+
+    fn check_known(&mut self, path: &str, value: &Value, known: &[&str]) -> bool {
+        let Value::Table(table) = value else {
+            self.error(path, format!("{} should be a table", path));
+            return false;
+        };
+        for key in table.keys() {
+            if !known.contains(&key.as_str()) {
+                self.error(
+                    &format!("{}.{}", path, key),
+                    format!(
+                        "unknown field {} in {}, expected one of {}",
+                        key,
+                        path,
+                        known.join(", ")
+                    ),
+                );
+            }
+        }
+        true
+    }
+
+    /// Fields of each table of the array at `path`
+    fn check_items<T: DeserializeOwned>(&mut self, path: &str, value: Option<&Value>) {
+        if let Some(Value::Array(items)) = value {
+            for (index, item) in items.iter().enumerate() {
+                self.check_fields::<T>(&format!("{}[{}]", path, index), item);
+            }
+        }
+    }
+
+    fn check_server(&mut self, value: &Value, aliases: &[String], has_auth: bool) {
+        self.check_section::<ServerConfig>("server", value);
+        if let Some(cors) = value.get("cors") {
+            self.check_fields::<CorsConfig>("server.cors", cors);
+        }
+        if let Some(auth) = value.get("auth") {
+            self.check_auth("server.auth", auth, aliases);
+            if has_auth {
+                self.error(
+                    "server.auth",
+                    "auth is set in both [auth] and [server.auth]".to_string(),
+                );
+            }
+        }
+    }
+
+    /// Fields of the auth section at `path`, its types are checked by the caller
+    fn check_auth(&mut self, path: &str, value: &Value, aliases: &[String]) {
+        self.check_fields::<AuthConfig>(path, value);
+        let clients_path = format!("{}.clients", path);
+        self.check_items::<ClientKey>(&clients_path, value.get("clients"));
+
+        let clients = value.get("clients").and_then(Value::as_array);
+        for (index, client) in clients.into_iter().flatten().enumerate() {
+            let models = client.get("models").and_then(Value::as_array);
+            for (model_index, model) in models.into_iter().flatten().enumerate() {
+                let Some(model) = model.as_str() else {
+                    continue;
+                };
+                if model != "*" && !aliases.iter().any(|alias| alias == model) {
+                    self.warning(
+                        &format!("{}[{}].models[{}]", clients_path, index, model_index),
+                        format!("client key allows unknown model {}", model),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Unknown fields of a model table, or of a provider table with `extra`
+    /// fields, false if it is not a table
+    fn check_model_fields(&mut self, name: &str, value: &Value, extra: &[&str]) -> bool {
+        let known = [fields::<Model>(), extra].concat();
+        if !self.check_known(name, value, &known) {
+            return false;
+        }
+        let path = |field: &str| format!("{}.{}", name, field);
+        if let Some(retry) = value.get("retry") {
+            self.check_fields::<RetryPolicy>(&path("retry"), retry);
+        }
+        if let Some(balance) = value.get("balance") {
+            self.check_fields::<Balance>(&path("balance"), balance);
+        }
+        if let Some(pricing) = value.get("pricing") {
+            self.check_fields::<Pricing>(&path("pricing"), pricing);
+        }
+        if let Some(mock) = value.get("mock") {
+            self.check_fields::<MockScript>(&path("mock"), mock);
+        }
+        if let Some(options) = value.get("options") {
+            self.check_fields::<ModelOptions>(&path("options"), options);
+        }
+        if let Some(timeouts) = value.get("timeouts") {
+            self.check_fields::<Timeouts>(&path("timeouts"), timeouts);
+        }
+        self.check_items::<Endpoint>(&path("endpoints"), value.get("endpoints"));
+        true
+    }
+
+    fn check_model(&mut self, name: &str, value: &Value, aliases: &[String]) {
+        // an unknown provider is reported once, not again as a type error
+        let mut model = value.clone();
+        let provider = match value.get("provider") {
+            Some(provider) => match provider.clone().try_into::<ProviderName>() {
+                Ok(provider) => Some(provider),
+                Err(e) => {
+                    self.error(
+                        &format!("{}.provider", name),
+                        format!("unknown provider in {}: {}", name, e.message().trim()),
+                    );
+                    model["provider"] = Value::String(ProviderName::Mock.to_string());
+                    None
+                }
+            },
+            None => None,
+        };
+        self.check_types::<Model>(name, model);
+
+        self.check_urls(name, value, provider);
+        self.check_api_keys(name, value, provider);
+        self.check_fallbacks(name, value, aliases);
+    }
+
+    fn check_urls(&mut self, name: &str, value: &Value, provider: Option<ProviderName>) {
+        let endpoints = value.get("endpoints").and_then(Value::as_array);
+        let mut urls = vec![
+            (format!("{}.url", name), value.get("url")),
+            (
+                format!("{}.completion_url", name),
+                value.get("completion_url"),
+            ),
+        ];
+        for (index, endpoint) in endpoints.into_iter().flatten().enumerate() {
+            urls.push((
+                format!("{}.endpoints[{}].url", name, index),
+                endpoint.get("url"),
+            ));
+        }
+
+        for (path, url) in urls {
+            let Some(url) = url.and_then(Value::as_str) else {
+                continue;
+            };
+            // empty is the default of `url`, unresolved references are reported
+            if url.is_empty() || url.contains("${") {
+                continue;
+            }
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                Ok(parsed) => self.error(
+                    &path,
+                    format!("{} should be an http(s) URL, not {}", path, parsed.scheme()),
+                ),
+                Err(e) => self.error(&path, format!("{} is not a valid URL: {}", path, e)),
+            }
+        }
+
+        let has_url = value
+            .get("url")
+            .and_then(Value::as_str)
+            .is_some_and(|url| !url.is_empty());
+        let needs_url = !matches!(
+            provider,
+            None | Some(ProviderName::Replay) | Some(ProviderName::Mock)
+        );
+        if needs_url && !has_url && endpoints.is_none_or(Vec::is_empty) {
+            self.error(name, format!("{} has neither url nor endpoints", name));
+        }
+    }
+
+    /// Hosted providers reject requests without a key, a local
+    /// Xinference server usually has none. No provider takes an empty list.
+    fn check_api_keys(&mut self, name: &str, value: &Value, provider: Option<ProviderName>) {
+        let path = format!("{}.api_key", name);
+        if value
+            .get("api_key")
+            .and_then(Value::as_array)
+            .is_some_and(|keys| keys.is_empty())
+        {
+            self.error(&path, format!("{} is an empty list", path));
+            return;
+        }
+        if !matches!(
+            provider,
+            Some(ProviderName::Zhipu) | Some(ProviderName::DeepSeek)
+        ) {
+            return;
+        }
+        match value.get("api_key") {
+            Some(Value::String(key)) if key.trim().is_empty() => {
+                self.error(&path, format!("{} is empty", path));
+            }
+            Some(Value::Array(keys)) => {
+                for (index, key) in keys.iter().enumerate() {
+                    if key.as_str().is_some_and(|key| key.trim().is_empty()) {
+                        let path = format!("{}[{}]", path, index);
+                        self.error(&path, format!("{} is empty", path));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_fallbacks(&mut self, name: &str, value: &Value, aliases: &[String]) {
+        let fallbacks = value.get("fallbacks").and_then(Value::as_array);
+        let mut seen = Vec::new();
+        for (index, fallback) in fallbacks.into_iter().flatten().enumerate() {
+            let Some(fallback) = fallback.as_str() else {
+                continue;
+            };
+            let path = format!("{}.fallbacks[{}]", name, index);
+            if fallback == name {
+                self.warning(&path, format!("{} falls back to itself", name));
+            } else if !aliases.iter().any(|alias| alias == fallback) {
+                self.error(
+                    &path,
+                    format!("{} falls back to unknown model {}", name, fallback),
+                );
+            } else if seen.contains(&fallback) {
+                self.warning(&path, format!("{} falls back to {} twice", name, fallback));
+            }
+            seen.push(fallback);
+        }
+    }
+
+    /// Names of different models that can't be told apart, `model_names`
+    /// holds the alias of the model, the name and where it is declared
+    fn check_duplicates(&mut self, model_names: &[(String, String, String)]) {
+        let mut model_names = model_names
+            .iter()
+            .map(|(alias, name, path)| (self.line(path), alias, name, path))
+            .collect::<Vec<_>>();
+        // stable, the alias of a model stays before its other names
+        model_names.sort_by_key(|(line, ..)| *line);
+
+        let mut first: HashMap<String, (usize, &String, &String)> = HashMap::new();
+        for (line, alias, name, path) in model_names {
+            match first.get(&names::normalize(name)) {
+                Some((_, first_alias, _)) if alias == *first_alias && name != alias => {}
+                Some((first_line, first_alias, _)) if alias == *first_alias => self.error(
+                    path,
+                    format!(
+                        "model {} is declared twice, first on line {}",
+                        alias, first_line
+                    ),
+                ),
+                Some((first_line, first_alias, first_name))
+                    if name == alias && first_name == first_alias =>
+                {
+                    self.error(
+                        path,
+                        format!(
+                            "model {} is the same as {} on line {} once normalised",
+                            alias, first_alias, first_line
+                        ),
+                    )
+                }
+                Some((first_line, first_alias, first_name)) => self.error(
+                    path,
+                    format!(
+                        "name {} of model {} is the same as {} of model {} on line {} once normalised",
+                        name, alias, first_name, first_alias, first_line
+                    ),
+                ),
+                None => {
+                    first.insert(names::normalize(name), (line, alias, name));
+                }
+            }
+        }
+    }
+}
+
+/// Record the line of every key below `table`
+fn locate(table: &dyn TableLike, path: &str, contents: &str, lines: &mut HashMap<String, usize>) {
+    for (key, item) in table.iter() {
+        let path = match path.is_empty() {
+            true => key.to_string(),
+            false => format!("{}.{}", path, key),
+        };
+        let span = table
+            .get_key_value(key)
+            .and_then(|(key, _)| key.span())
+            .or_else(|| item.span());
+        if let Some(span) = span {
+            lines.insert(path.clone(), line_of(contents, span.start));
+        }
+
+        match item {
+            Item::Table(table) => locate(table, &path, contents, lines),
+            Item::ArrayOfTables(tables) => {
+                for (index, table) in tables.iter().enumerate() {
+                    let path = format!("{}[{}]", path, index);
+                    if let Some(span) = table.span() {
+                        lines.insert(path.clone(), line_of(contents, span.start));
+                    }
+                    locate(table, &path, contents, lines);
+                }
+            }
+            Item::Value(value) => locate_value(value, &path, contents, lines),
+            Item::None => {}
+        }
+    }
+}
+
+fn locate_value(
+    value: &toml_edit::Value,
+    path: &str,
+    contents: &str,
+    lines: &mut HashMap<String, usize>,
+) {
+    match value {
+        toml_edit::Value::InlineTable(table) => locate(table, path, contents, lines),
+        toml_edit::Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                let path = format!("{}[{}]", path, index);
+                if let Some(span) = item.span() {
+                    lines.insert(path.clone(), line_of(contents, span.start));
+                }
+                locate_value(item, &path, contents, lines);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn line_of(contents: &str, offset: usize) -> usize {
+    contents[..offset.min(contents.len())].matches('\n').count() + 1
+}
+
+/// Fields of a struct, as its `Deserialize` declares them to serde
+fn fields<T: DeserializeOwned>() -> &'static [&'static str] {
+    struct Fields<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> de::Deserializer<'de> for Fields<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("fields read"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(Fields(&mut fields));
+    fields
+}
@@ -1,37 +1,288 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use serde::de::{DeserializeOwned, IntoDeserializer};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
+use toml::{Table, Value};
+use toml_edit::ImDocument;
+use tracing::warn;
 
-use crate::structs::config::Model;
+use crate::interpolate;
+use crate::logging;
+use crate::names;
+use crate::structs::config::{
+    AccountingConfig, AuthConfig, CacheConfig, Model, ProviderName, ServerConfig, TranscriptConfig,
+};
 
+/// The config file, every table that is not a known section is a model.
+/// Models may also be declared as `[models.<alias>]` referencing one of the
+/// `[providers.<name>]`, those are expanded to flat model tables on load.
 #[derive(Debug, Serialize, Deserialize)]
-pub struct Config(HashMap<String, Model>);
+pub struct Config {
+    #[serde(default)]
+    pub server: ServerConfig,
+    /// Client keys of the proxy, no authentication if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthConfig>,
+    #[serde(default)]
+    pub accounting: AccountingConfig,
+    #[serde(default)]
+    pub transcripts: TranscriptConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(flatten)]
+    models: HashMap<String, Model>,
+}
 
 impl Config {
     pub fn get_model(&self, name: &str) -> Option<&Model> {
-        self.0.get(name)
+        self.models.get(name)
+    }
+
+    /// Alias of the model a client asks for by any of its names
+    pub fn resolve(&self, name: &str) -> Option<&str> {
+        names::resolve(&self.models, name)
     }
 
     pub fn models(&self) -> &HashMap<String, Model> {
-        &self.0
+        &self.models
     }
 
     pub fn from_file(path: &str) -> Result<Self> {
         let contents = fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&contents)?;
+        let mut value = contents.parse::<toml::Value>()?;
+
+        // resolved secrets are masked like keys, also in the errors below
+        let secrets = interpolate::resolve(&mut value)?;
+        logging::register_secrets(secrets.iter().map(String::as_str));
+
+        let (models, errors) = expand_models(&value);
+        if let Some((_, error)) = errors.into_iter().next() {
+            return Err(anyhow!(logging::redact(&error)));
+        }
+        if let Value::Table(table) = &mut value {
+            table.remove("providers");
+            table.remove("models");
+            for (alias, model) in models {
+                if table.contains_key(&alias) {
+                    return Err(anyhow!(
+                        "model {} is declared both as [{}] and [models.{}]",
+                        alias,
+                        alias,
+                        alias
+                    ));
+                }
+                table.insert(alias, model);
+            }
+        }
+
+        let mut config: Config = value.try_into().map_err(|e: toml::de::Error| {
+            let message = locate(&contents, e.message()).unwrap_or_else(|| e.to_string());
+            anyhow!(logging::redact(&message))
+        })?;
+        if let Some(auth) = config.server.auth.take() {
+            if config.auth.is_some() {
+                return Err(anyhow!("auth is set in both [auth] and [server.auth]"));
+            }
+            config.auth = Some(auth);
+        }
+        // a model without a key has `api_key = ""`, not a list to pick from
+        if let Some(alias) = config
+            .models
+            .iter()
+            .find_map(|(alias, model)| model.api_key.keys().is_empty().then_some(alias))
+        {
+            return Err(anyhow!("{}.api_key is an empty list", alias));
+        }
+        config.register_secrets();
         Ok(config)
     }
 
+    /// The model followed by its fallbacks, unknown aliases are skipped
+    pub fn fallback_chain<'a>(&'a self, model_name: &'a str) -> Vec<(&'a str, &'a Model)> {
+        let mut chain = Vec::new();
+        let Some(model) = self.get_model(model_name) else {
+            return chain;
+        };
+        chain.push((model_name, model));
+
+        for alias in &model.fallbacks {
+            if chain.iter().any(|(name, _)| name == alias) {
+                continue;
+            }
+            match self.get_model(alias) {
+                Some(fallback) => chain.push((alias.as_str(), fallback)),
+                None => warn!("Unknown fallback {} of model {}", alias, model_name),
+            }
+        }
+
+        chain
+    }
+
+    /// Mask the upstream and client keys in the logs
+    fn register_secrets(&self) {
+        let model_keys = self.models.values().flat_map(|model| model.api_key.keys());
+        let client_keys = self.auth.iter().flat_map(|auth| &auth.clients);
+        logging::register_secrets(
+            model_keys
+                .map(String::as_str)
+                .chain(client_keys.map(|client| client.key.as_str())),
+        );
+    }
+
     pub fn contains_model(&self, model_name: &str) -> bool {
-        self.0.iter().any(|(model, _)| model == model_name)
+        self.models.iter().any(|(model, _)| model == model_name)
+    }
+}
+
+/// The error `message` with its line and column in the file. The resolved
+/// value has no spans, so each table of the file is deserialized again as it
+/// is written until one fails the same way. `None` if none does, e.g. when the
+/// value at fault comes from a reference or a `[models.<alias>]` table.
+fn locate(contents: &str, message: &str) -> Option<String> {
+    let document = ImDocument::parse(contents).ok()?;
+    let located = document.iter().find_map(|(key, item)| {
+        let value = item.clone().into_value().ok()?;
+        let error = match key {
+            "server" => error_of::<ServerConfig>(value),
+            "auth" => error_of::<AuthConfig>(value),
+            "accounting" => error_of::<AccountingConfig>(value),
+            "transcripts" => error_of::<TranscriptConfig>(value),
+            "cache" => error_of::<CacheConfig>(value),
+            "providers" | "models" => None,
+            _ => error_of::<Model>(value),
+        }?;
+        let span = error.span().filter(|_| error.message() == message)?;
+        let before = &contents[..span.start.min(contents.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        Some(format!("line {}, column {}: {}", line, column, message))
+    });
+    located
+}
+
+fn error_of<T: DeserializeOwned>(value: toml_edit::Value) -> Option<toml_edit::de::Error> {
+    T::deserialize(value.into_deserializer()).err()
+}
+
+/// Path in the config file and message of a problem
+pub type PathError = (String, String);
+
+/// The `[models.<alias>]` tables as flat model tables: the table of their
+/// `[providers.<name>]` with the fields of the model on top. `provider` names
+/// a provider section, or a kind of provider that needs no section. The kind
+/// of a section is its `kind`, or its name. `model_name` defaults to the alias.
+/// Problems are returned with their path, the models with one are left out.
+pub fn expand_models(value: &Value) -> (Vec<(String, Value)>, Vec<PathError>) {
+    let mut models = Vec::new();
+    let mut errors = Vec::new();
+    let empty = Table::new();
+
+    let providers = match value.get("providers") {
+        None => &empty,
+        Some(Value::Table(providers)) => providers,
+        Some(_) => {
+            errors.push((
+                "providers".to_string(),
+                "providers should be a table".to_string(),
+            ));
+            &empty
+        }
+    };
+    let mut kinds = HashMap::new();
+    for (name, provider) in providers {
+        let path = format!("providers.{}", name);
+        let Value::Table(provider) = provider else {
+            errors.push((path.clone(), format!("{} should be a table", path)));
+            continue;
+        };
+        let kind = match provider.get("kind") {
+            Some(Value::String(kind)) => kind.as_str(),
+            Some(_) => {
+                errors.push((
+                    format!("{}.kind", path),
+                    format!("{}.kind should be a string", path),
+                ));
+                continue;
+            }
+            None => name.as_str(),
+        };
+        match kind.parse::<ProviderName>() {
+            Ok(kind) => {
+                kinds.insert(name.as_str(), kind);
+            }
+            Err(_) => errors.push((
+                path.clone(),
+                format!(
+                    "{} has no valid kind, expected one of zhipu, deepseek, xinference, replay, mock",
+                    path
+                ),
+            )),
+        }
+    }
+
+    let declared = match value.get("models") {
+        None => &empty,
+        Some(Value::Table(declared)) => declared,
+        Some(_) => {
+            errors.push(("models".to_string(), "models should be a table".to_string()));
+            &empty
+        }
+    };
+    for (alias, model) in declared {
+        let path = format!("models.{}", alias);
+        let Value::Table(model) = model else {
+            errors.push((path.clone(), format!("{} should be a table", path)));
+            continue;
+        };
+        let Some(provider) = model.get("provider").and_then(Value::as_str) else {
+            errors.push((path.clone(), format!("{} needs a provider", path)));
+            continue;
+        };
+
+        let (mut merged, kind) = match (providers.get(provider), kinds.get(provider)) {
+            (Some(Value::Table(table)), Some(kind)) => (table.clone(), *kind),
+            // reported with the provider
+            (Some(_), _) => continue,
+            (None, _) => match provider.parse::<ProviderName>() {
+                Ok(kind) => (Table::new(), kind),
+                Err(_) => {
+                    errors.push((
+                        format!("{}.provider", path),
+                        format!("{} references unknown provider {}", path, provider),
+                    ));
+                    continue;
+                }
+            },
+        };
+        merged.remove("kind");
+        merge(&mut merged, model);
+        merged.insert("provider".to_string(), Value::String(kind.to_string()));
+        merged
+            .entry("model_name")
+            .or_insert_with(|| Value::String(alias.clone()));
+        models.push((alias.clone(), Value::Table(merged)));
+    }
+
+    (models, errors)
+}
+
+/// Fields of `over` replace those of `base`, tables are merged
+fn merge(base: &mut Table, over: &Table) {
+    for (key, value) in over {
+        match (base.get_mut(key), value) {
+            (Some(Value::Table(base)), Value::Table(over)) => merge(base, over),
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
     }
 }
 
 pub fn check_model_name(model_name: &str, config_path: &str) -> bool {
     let config_result = Config::from_file(config_path);
     match config_result {
-        Ok(config) => config.contains_model(model_name),
+        Ok(config) => config.resolve(model_name).is_some(),
         Err(error) => {
             println!("Error reading config file: {}", error);
             false
@@ -0,0 +1,200 @@
+/// Cost of the requests, priced from the upstream usage with the `pricing` of
+/// the model that served them. Costs are summed per day, client and model and
+/// saved to the `costs_file` of `[accounting]`.
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+use crate::persist::{self, Persisted, Writes};
+use crate::structs::config::Pricing;
+use crate::structs::openai::Usage;
+
+/// Client of the requests made without authentication
+pub const ANONYMOUS: &str = "anonymous";
+
+/// Requests of a client to a model on a day
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CostRecord {
+    /// `%Y-%m-%d`, UTC
+    pub day: String,
+    pub client: String,
+    /// Alias of the model that served the requests
+    pub model: String,
+    pub currency: String,
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost: f64,
+}
+
+/// How records are summed up in a report
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    #[default]
+    Model,
+    Client,
+    Day,
+}
+
+/// A line of a report, the records sharing `key` and `currency`
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct CostRow {
+    pub key: String,
+    pub currency: String,
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost: f64,
+}
+
+#[derive(Default)]
+struct LedgerState {
+    /// File the records are loaded from and saved to
+    path: Option<String>,
+    /// Keyed by day, client and model
+    records: BTreeMap<(String, String, String), CostRecord>,
+    writes: Writes,
+}
+
+#[derive(Default)]
+pub struct Ledger {
+    state: Arc<Mutex<LedgerState>>,
+}
+
+impl Ledger {
+    /// Add the cost of a request served by `model` to the ledger in `path`
+    pub fn record(&self, path: &str, client: &str, model: &str, pricing: &Pricing, usage: &Usage) {
+        let day = Utc::now().format("%Y-%m-%d").to_string();
+        let mut state = self.state.lock().unwrap();
+        state.load(path);
+
+        let record = state
+            .records
+            .entry((day.clone(), client.to_string(), model.to_string()))
+            .or_insert_with(|| CostRecord {
+                day,
+                client: client.to_string(),
+                model: model.to_string(),
+                currency: pricing.currency.clone(),
+                ..Default::default()
+            });
+        record.requests += 1;
+        record.input_tokens += usage.prompt_tokens;
+        record.output_tokens += usage.completion_tokens;
+        record.cost += pricing.cost(usage.prompt_tokens, usage.completion_tokens);
+
+        persist::save(&mut *state, &self.state);
+    }
+
+    /// Every record of the ledger in `path`
+    pub fn records(&self, path: &str) -> Vec<CostRecord> {
+        let mut state = self.state.lock().unwrap();
+        state.load(path);
+        state.records.values().cloned().collect()
+    }
+}
+
+impl LedgerState {
+    /// Load the saved records the first time `path` is used
+    fn load(&mut self, path: &str) {
+        if self.path.as_deref() == Some(path) {
+            return;
+        }
+        self.path = Some(path.to_string());
+        self.records.clear();
+
+        match load(path) {
+            Ok(records) => {
+                for record in records {
+                    let key = (
+                        record.day.clone(),
+                        record.client.clone(),
+                        record.model.clone(),
+                    );
+                    self.records.insert(key, record);
+                }
+            }
+            Err(e) => warn!("Ignoring unreadable costs file {}: {}", path, e),
+        }
+    }
+}
+
+impl Persisted for LedgerState {
+    type Saved = Vec<CostRecord>;
+    const NAME: &'static str = "costs";
+
+    fn writes(&mut self) -> &mut Writes {
+        &mut self.writes
+    }
+
+    fn snapshot(&self) -> Option<(String, Self::Saved)> {
+        let path = self.path.clone()?;
+        Some((path, self.records.values().cloned().collect()))
+    }
+}
+
+/// Read the records saved in `path`, none if the file doesn't exist yet
+pub fn load(path: &str) -> Result<Vec<CostRecord>> {
+    Ok(persist::read(path)?.unwrap_or_default())
+}
+
+/// Sum the records by model, client or day, ordered by key
+pub fn report(records: &[CostRecord], by: GroupBy) -> Vec<CostRow> {
+    let mut rows = BTreeMap::<(String, String), CostRow>::new();
+    for record in records {
+        let key = match by {
+            GroupBy::Model => &record.model,
+            GroupBy::Client => &record.client,
+            GroupBy::Day => &record.day,
+        };
+        let row = rows
+            .entry((key.clone(), record.currency.clone()))
+            .or_insert_with(|| CostRow {
+                key: key.clone(),
+                currency: record.currency.clone(),
+                ..Default::default()
+            });
+        row.requests += record.requests;
+        row.input_tokens += record.input_tokens;
+        row.output_tokens += record.output_tokens;
+        row.cost += record.cost;
+    }
+    rows.into_values().collect()
+}
+
+/// The report as CSV with a header line, the first column is named after `by`
+pub fn to_csv(rows: &[CostRow], by: GroupBy) -> String {
+    let key = match by {
+        GroupBy::Model => "model",
+        GroupBy::Client => "client",
+        GroupBy::Day => "day",
+    };
+    let mut csv = format!(
+        "{},currency,requests,input_tokens,output_tokens,cost\n",
+        key
+    );
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{:.6}\n",
+            csv_field(&row.key),
+            csv_field(&row.currency),
+            row.requests,
+            row.input_tokens,
+            row.output_tokens,
+            row.cost
+        ));
+    }
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
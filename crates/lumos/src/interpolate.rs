@@ -0,0 +1,132 @@
+/// References to the environment and to secret files in the config file.
+/// `${NAME}` and `${NAME:-default}` are replaced anywhere in a string with the
+/// environment variable, `$${` stands for a literal `${`. A string starting
+/// with `file:` is replaced with the contents of the file, without the final
+/// newline, e.g. `file:/run/secrets/deepseek_key`.
+/// The contents of files and the variables substituted into an `api_key` are
+/// secrets, other values such as hosts are not.
+use anyhow::{anyhow, Error, Result};
+use toml::Value;
+
+const FILE_PREFIX: &str = "file:";
+
+/// Resolve the references of every string in `value`, returning the secrets
+/// among the substituted values so they can be kept out of logs and errors
+pub fn resolve(value: &mut Value) -> Result<Vec<String>> {
+    let (secrets, mut errors) = resolve_all(value);
+    match errors.is_empty() {
+        true => Ok(secrets),
+        false => Err(errors.remove(0).1),
+    }
+}
+
+/// Like `resolve`, but going on after an error, the strings that can't be
+/// resolved are left as they are. The errors come with the path of the string.
+pub fn resolve_all(value: &mut Value) -> (Vec<String>, Vec<(String, Error)>) {
+    let mut secrets = Vec::new();
+    let mut errors = Vec::new();
+    resolve_value(value, "", &mut secrets, &mut errors);
+    (secrets, errors)
+}
+
+fn resolve_value(
+    value: &mut Value,
+    path: &str,
+    secrets: &mut Vec<String>,
+    errors: &mut Vec<(String, Error)>,
+) {
+    match value {
+        Value::String(text) => match resolve_string(text, path, secrets) {
+            Ok(resolved) => *text = resolved,
+            Err(e) => errors.push((path.to_string(), e)),
+        },
+        Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                resolve_value(item, &format!("{}[{}]", path, index), secrets, errors);
+            }
+        }
+        Value::Table(table) => {
+            for (key, item) in table.iter_mut() {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                resolve_value(item, &path, secrets, errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn resolve_string(text: &str, path: &str, secrets: &mut Vec<String>) -> Result<String> {
+    if let Some(file) = text.strip_prefix(FILE_PREFIX) {
+        let contents = std::fs::read_to_string(file).map_err(|e| {
+            anyhow!(
+                "secret file {} referenced by {} cannot be read: {}",
+                file,
+                path,
+                e.kind()
+            )
+        })?;
+        let secret = contents.strip_suffix('\n').unwrap_or(&contents);
+        let secret = secret.strip_suffix('\r').unwrap_or(secret).to_string();
+        secrets.push(secret.clone());
+        return Ok(secret);
+    }
+
+    let is_key = is_api_key(path);
+    let mut resolved = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        // `$${` is an escaped `${`
+        if rest[..start].ends_with('$') {
+            resolved.push_str(&rest[..start - 1]);
+            resolved.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        resolved.push_str(&rest[..start]);
+
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unterminated ${{ in {}", path))?;
+        let reference = &rest[start + 2..start + end];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        if name.is_empty() {
+            return Err(anyhow!("empty variable name in {}", path));
+        }
+
+        let value = match (std::env::var(name), default) {
+            (Ok(value), Some(default)) if value.is_empty() => default.to_string(),
+            (Ok(value), _) => {
+                if is_key {
+                    secrets.push(value.clone());
+                }
+                value
+            }
+            (Err(_), Some(default)) => default.to_string(),
+            (Err(_), None) => {
+                return Err(anyhow!(
+                    "environment variable {} referenced by {} is not set",
+                    name,
+                    path
+                ))
+            }
+        };
+        resolved.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+/// Whether the string at `path` is an upstream key, e.g. `glm-4-plus.api_key`
+/// or `providers.zhipu.api_key[1]`
+fn is_api_key(path: &str) -> bool {
+    let field = path.trim_end_matches(|c: char| c == ']' || c == '[' || c.is_ascii_digit());
+    field == "api_key" || field.ends_with(".api_key")
+}
@@ -1,4 +1,16 @@
+pub mod admin;
 pub mod app;
+pub mod auth;
+pub mod check;
 pub mod config;
+pub mod costs;
+pub mod error;
+pub mod interpolate;
+pub mod limits;
+pub mod logging;
+pub mod metrics;
+pub mod names;
 pub mod ollama;
+pub mod persist;
 pub mod structs;
+pub mod transcripts;
@@ -0,0 +1,254 @@
+/// Per-client request rate, concurrent streams and token budgets.
+/// Token consumption comes from the upstream usage and is saved to the
+/// `usage_file` of `[auth]`, so budgets survive restarts.
+use chrono::{Datelike, Duration as ChronoDuration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::persist::{self, Persisted, Writes};
+use crate::structs::config::ClientKey;
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// Token consumption of a client, the part that is persisted
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Consumption {
+    /// `%Y-%m-%d` of `day_tokens`
+    pub day: String,
+    pub day_tokens: u64,
+    /// `%Y-%m` of `month_tokens`
+    pub month: String,
+    pub month_tokens: u64,
+    pub total_tokens: u64,
+    pub total_requests: u64,
+    #[serde(skip_deserializing)]
+    pub streams: usize,
+    #[serde(skip_deserializing)]
+    pub requests_last_minute: usize,
+}
+
+impl Consumption {
+    /// Reset the counters of a past day or month
+    fn roll_over(&mut self) {
+        let now = Utc::now();
+        let day = now.format("%Y-%m-%d").to_string();
+        let month = now.format("%Y-%m").to_string();
+        if self.day != day {
+            self.day = day;
+            self.day_tokens = 0;
+        }
+        if self.month != month {
+            self.month = month;
+            self.month_tokens = 0;
+        }
+    }
+}
+
+#[derive(Default)]
+struct ClientState {
+    consumption: Consumption,
+    recent: VecDeque<Instant>,
+}
+
+#[derive(Default)]
+struct LimitsState {
+    /// File the consumption is loaded from and saved to
+    path: Option<String>,
+    clients: BTreeMap<String, ClientState>,
+    writes: Writes,
+}
+
+/// A request refused by a limit
+#[derive(Debug)]
+pub struct Limited {
+    pub message: String,
+    pub retry_after: Duration,
+}
+
+#[derive(Default)]
+pub struct Limits {
+    state: Arc<Mutex<LimitsState>>,
+}
+
+/// Counts a stream of the client until dropped
+pub struct StreamGuard {
+    state: Arc<Mutex<LimitsState>>,
+    client: String,
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(client) = state.clients.get_mut(&self.client) {
+            client.consumption.streams = client.consumption.streams.saturating_sub(1);
+        }
+    }
+}
+
+impl Limits {
+    /// Admit a request of the client, or tell when to retry
+    pub fn acquire(&self, client: &ClientKey, usage_file: &str) -> Result<StreamGuard, Limited> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.load(usage_file);
+
+        let entry = state.clients.entry(client.name.clone()).or_default();
+        entry.consumption.roll_over();
+        while entry
+            .recent
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= MINUTE)
+        {
+            entry.recent.pop_front();
+        }
+
+        let consumption = &entry.consumption;
+        if let Some(limit) = client.requests_per_minute {
+            if entry.recent.len() >= limit as usize {
+                // none to wait for with a limit of 0, which refuses every request
+                let retry_after = match entry.recent.front() {
+                    Some(oldest) => MINUTE.saturating_sub(now.duration_since(*oldest)),
+                    None => MINUTE,
+                };
+                return Err(Limited {
+                    message: format!("{} requests per minute exceeded", limit),
+                    retry_after,
+                });
+            }
+        }
+        if let Some(limit) = client.max_concurrent_streams {
+            if consumption.streams >= limit as usize {
+                return Err(Limited {
+                    message: format!("{} concurrent streams exceeded", limit),
+                    retry_after: Duration::from_secs(1),
+                });
+            }
+        }
+        if let Some(limit) = client.daily_tokens {
+            if consumption.day_tokens >= limit {
+                return Err(Limited {
+                    message: format!("daily budget of {} tokens exceeded", limit),
+                    retry_after: until_next_day(),
+                });
+            }
+        }
+        if let Some(limit) = client.monthly_tokens {
+            if consumption.month_tokens >= limit {
+                return Err(Limited {
+                    message: format!("monthly budget of {} tokens exceeded", limit),
+                    retry_after: until_next_month(),
+                });
+            }
+        }
+
+        entry.recent.push_back(now);
+        entry.consumption.streams += 1;
+        entry.consumption.total_requests += 1;
+
+        Ok(StreamGuard {
+            state: self.state.clone(),
+            client: client.name.clone(),
+        })
+    }
+
+    /// Add the tokens of a completed request to the budgets of the client
+    pub fn record(&self, client: &str, tokens: u64) {
+        let mut state = self.state.lock().unwrap();
+        let entry = state.clients.entry(client.to_string()).or_default();
+        entry.consumption.roll_over();
+        entry.consumption.day_tokens += tokens;
+        entry.consumption.month_tokens += tokens;
+        entry.consumption.total_tokens += tokens;
+
+        if state.path.is_some() {
+            persist::save(&mut *state, &self.state);
+        }
+    }
+
+    /// Current consumption of every client
+    pub fn consumption(&self) -> BTreeMap<String, Consumption> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state
+            .clients
+            .iter_mut()
+            .map(|(name, client)| {
+                client.consumption.roll_over();
+                let mut consumption = client.consumption.clone();
+                consumption.requests_last_minute = client
+                    .recent
+                    .iter()
+                    .filter(|at| now.duration_since(**at) < MINUTE)
+                    .count();
+                (name.clone(), consumption)
+            })
+            .collect()
+    }
+}
+
+impl LimitsState {
+    /// Load the saved consumption the first time `path` is used
+    fn load(&mut self, path: &str) {
+        if self.path.as_deref() == Some(path) {
+            return;
+        }
+        self.path = Some(path.to_string());
+
+        match persist::read::<BTreeMap<String, Consumption>>(path) {
+            Ok(saved) => {
+                for (name, consumption) in saved.unwrap_or_default() {
+                    let client = self.clients.entry(name).or_default();
+                    let streams = client.consumption.streams;
+                    client.consumption = Consumption {
+                        streams,
+                        ..consumption
+                    };
+                }
+            }
+            Err(e) => warn!("Ignoring unreadable usage file {}: {}", path, e),
+        }
+    }
+}
+
+impl Persisted for LimitsState {
+    type Saved = BTreeMap<String, Consumption>;
+    const NAME: &'static str = "usage";
+
+    fn writes(&mut self) -> &mut Writes {
+        &mut self.writes
+    }
+
+    fn snapshot(&self) -> Option<(String, Self::Saved)> {
+        let path = self.path.clone()?;
+        let consumption = self
+            .clients
+            .iter()
+            .map(|(name, client)| (name.clone(), client.consumption.clone()))
+            .collect();
+        Some((path, consumption))
+    }
+}
+
+fn until_next_day() -> Duration {
+    let now = Utc::now();
+    let tomorrow = now.date_naive() + ChronoDuration::days(1);
+    until(tomorrow)
+}
+
+fn until_next_month() -> Duration {
+    let today = Utc::now().date_naive();
+    let next_month = if today.month() == 12 {
+        NaiveDate::from_ymd_opt(today.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(today.year(), today.month() + 1, 1)
+    };
+    until(next_month.unwrap_or(today))
+}
+
+fn until(date: NaiveDate) -> Duration {
+    let start = date.and_hms_opt(0, 0, 0).unwrap().and_utc();
+    (start - Utc::now()).to_std().unwrap_or_default()
+}
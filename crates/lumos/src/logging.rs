@@ -0,0 +1,164 @@
+/// Log output of the proxy: plain text or one JSON object per line.
+/// API keys of the loaded config are masked in every record, and message
+/// contents are left out when `redact_content` is set.
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::{warn, Event, Subscriber};
+use tracing_subscriber::field::MakeExt;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::{self, FormatEvent, FormatFields};
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::ollama::mask_key;
+
+/// Target of the per-request records
+pub const REQUEST_TARGET: &str = "lumos::request";
+
+/// Shorter values are not taken for keys, masking them would mangle words
+const MIN_SECRET_LEN: usize = 8;
+
+static SECRETS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+static REDACT_CONTENT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Install the global subscriber
+pub fn init(level: LevelFilter, format: LogFormat, redact_content: bool) {
+    REDACT_CONTENT.store(redact_content, Ordering::Relaxed);
+
+    let builder = tracing_subscriber::fmt().with_max_level(level);
+    match format {
+        LogFormat::Text => builder
+            .fmt_fields(
+                format::debug_fn(|writer, field, value| {
+                    let value = redact(&format!("{:?}", value));
+                    if field.name() == "message" {
+                        write!(writer, "{}", value)
+                    } else {
+                        write!(writer, "{}={}", field, value)
+                    }
+                })
+                .delimited(" "),
+            )
+            .init(),
+        LogFormat::Json => builder.event_format(JsonFormat).init(),
+    }
+}
+
+/// Keys to mask wherever they show up in a log record
+pub fn register_secrets<'a>(secrets: impl IntoIterator<Item = &'a str>) {
+    let mut known = SECRETS.lock().unwrap();
+    for secret in secrets {
+        if secret.len() >= MIN_SECRET_LEN && !known.contains(secret) {
+            known.insert(secret.to_string());
+        }
+    }
+}
+
+/// `text` with every registered key masked
+pub fn redact(text: &str) -> String {
+    let secrets = SECRETS.lock().unwrap();
+    let mut text = text.to_string();
+    for secret in secrets.iter() {
+        if text.contains(secret.as_str()) {
+            text = text.replace(secret.as_str(), &mask_key(secret));
+        }
+    }
+    text
+}
+
+/// ID of a request in its log record
+pub fn request_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Record of a request refused before it was dispatched, e.g. by the
+/// authentication or the limits of the client
+pub fn rejected(client: &str, model: Option<&str>, status: StatusCode, error: &str) {
+    warn!(
+        target: REQUEST_TARGET,
+        request_id = %request_id(),
+        client,
+        model,
+        status = status.as_u16(),
+        error,
+        "request rejected"
+    );
+}
+
+/// Message content as it may be logged
+pub fn content(text: &str) -> String {
+    if REDACT_CONTENT.load(Ordering::Relaxed) {
+        "[redacted]".to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// One JSON object per event with the timestamp, level, target and fields
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut record = Map::new();
+        record.insert("timestamp".to_string(), Utc::now().to_rfc3339().into());
+        record.insert("level".to_string(), metadata.level().to_string().into());
+        record.insert("target".to_string(), metadata.target().into());
+
+        let mut fields = JsonFields(record);
+        event.record(&mut fields);
+        writeln!(writer, "{}", Value::Object(fields.0))
+    }
+}
+
+struct JsonFields(Map<String, Value>);
+
+impl Visit for JsonFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0
+            .insert(field.name().to_string(), redact(value).into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let value = redact(&format!("{:?}", value));
+        self.0.insert(field.name().to_string(), value.into());
+    }
+}
@@ -4,54 +4,127 @@ use anyhow::Result;
 use axum::serve;
 
 use lumos::app::create_app;
-use lumos::config::check_model_name;
+use lumos::check::{self, Severity};
+use lumos::config::{check_model_name, Config};
+use lumos::costs::{self, GroupBy};
+use lumos::logging::{self, LogFormat};
+use lumos::ollama::run_health_checks;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use lumos::structs::app::AppState;
 use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Name of the model to use
-    model_name: String,
+    #[arg(required = true)]
+    model_name: Option<String>,
 
-    /// Server host address
-    #[arg(long, default_value = "localhost")]
-    host: String,
+    /// Server host address, `host` of `[server]` or localhost by default
+    #[arg(long)]
+    host: Option<String>,
 
-    /// Server port
-    #[arg(short, long, default_value_t = 11434)]
-    port: u16,
+    /// Server port, `port` of `[server]` or 11434 by default
+    #[arg(short, long)]
+    port: Option<u16>,
 
     /// Path to the Toml configuration file
     #[arg(short, long, default_value = "keys.toml")]
     config_file: String,
+
+    /// Most verbose level logged: off, error, warn, info, debug or trace
+    #[arg(long, default_value = "info")]
+    log_level: LevelFilter,
+
+    /// Log as plain text or one JSON object per line
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Leave message contents out of the logs
+    #[arg(long)]
+    redact_messages: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report the cost of the requests
+    Costs {
+        /// Sum the costs by model, client or day
+        #[arg(long, value_enum, default_value_t = GroupBy::Model)]
+        by: GroupBy,
+
+        /// Print CSV instead of a table
+        #[arg(long)]
+        csv: bool,
+
+        /// Path to the Toml configuration file
+        #[arg(short, long, default_value = "keys.toml")]
+        config_file: String,
+    },
+    /// Work with the configuration file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Report every problem of the configuration file, with its line
+    Check {
+        /// Path to the Toml configuration file
+        #[arg(short, long, default_value = "keys.toml")]
+        config_file: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
-
     let cli = Cli::parse();
+    logging::init(cli.log_level, cli.log_format, cli.redact_messages);
 
-    if !check_model_name(&cli.model_name, &cli.config_file) {
+    match cli.command {
+        Some(Command::Costs {
+            by,
+            csv,
+            config_file,
+        }) => return print_costs(by, csv, &config_file),
+        Some(Command::Config {
+            command: ConfigCommand::Check { config_file },
+        }) => return check_config(&config_file),
+        None => {}
+    }
+    let model_name = cli.model_name.unwrap_or_default();
+
+    if !check_model_name(&model_name, &cli.config_file) {
         eprintln!(
-            "Model name {} is not available in config file {}",
-            cli.model_name, cli.config_file
+            "Model name {} is not available in config file {}, see `lumos config check -c {}`",
+            model_name, cli.config_file, cli.config_file
         );
         std::process::exit(1);
     }
 
+    let config = Config::from_file(&cli.config_file)?;
+    // the model is served under its alias, whatever name it was started with
+    let model_name = config.resolve(&model_name).unwrap_or_default().to_string();
+    let server = config.server;
+    let host = cli.host.or(server.host).unwrap_or("localhost".to_string());
+    let port = cli.port.or(server.port).unwrap_or(11434);
+
     // Save the model name and config path in the app state
-    let app_state = Arc::new(AppState {
-        model_name: cli.model_name,
-        config_path: cli.config_file,
-    });
+    let app_state = Arc::new(AppState::new(model_name, cli.config_file));
+
+    tokio::spawn(run_health_checks(app_state.clone()));
 
     let app = create_app(app_state).await;
 
-    let addr = format!("{}:{}", cli.host, cli.port);
+    let addr = format!("{}:{}", host, port);
     info!("listening on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(&addr).await?;
@@ -59,3 +132,58 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+fn check_config(config_file: &str) -> Result<()> {
+    let diagnostics = check::check_file(config_file)?;
+    for diagnostic in &diagnostics {
+        let place = match diagnostic.line {
+            0 => config_file.to_string(),
+            line => format!("{}:{}", config_file, line),
+        };
+        println!("{}: {}: {}", place, diagnostic.severity, diagnostic.message);
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        eprintln!("{} has {} error(s)", config_file, errors);
+        std::process::exit(1);
+    }
+    println!("{} is valid", config_file);
+    Ok(())
+}
+
+fn print_costs(by: GroupBy, csv: bool, config_file: &str) -> Result<()> {
+    let config = Config::from_file(config_file)?;
+    let records = costs::load(&config.accounting.costs_file)?;
+    let rows = costs::report(&records, by);
+
+    if csv {
+        print!("{}", costs::to_csv(&rows, by));
+        return Ok(());
+    }
+
+    let width = rows
+        .iter()
+        .map(|row| row.key.len())
+        .max()
+        .unwrap_or(0)
+        .max(8);
+    println!(
+        "{:<width$}  {:>8}  {:>12}  {:>12}  {:>14}",
+        format!("{:?}", by).to_lowercase(),
+        "requests",
+        "input",
+        "output",
+        "cost"
+    );
+    for row in rows {
+        println!(
+            "{:<width$}  {:>8}  {:>12}  {:>12}  {:>10.4} {}",
+            row.key, row.requests, row.input_tokens, row.output_tokens, row.cost, row.currency
+        );
+    }
+    Ok(())
+}
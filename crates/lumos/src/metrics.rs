@@ -0,0 +1,253 @@
+/// Prometheus metrics of the upstream requests, exported by `GET /metrics`
+/// in the text exposition format. Series are labelled with the alias of the
+/// model that served the request and its provider.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Upper bounds in seconds of the latency histograms
+const BUCKETS: [f64; 12] = [
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+const REQUESTS: &str = "lumos_requests_total";
+const ERRORS: &str = "lumos_errors_total";
+const RETRIES: &str = "lumos_retries_total";
+const TOKENS: &str = "lumos_tokens_total";
+const STREAMS: &str = "lumos_streams_in_flight";
+const FIRST_TOKEN: &str = "lumos_time_to_first_token_seconds";
+const DURATION: &str = "lumos_request_duration_seconds";
+const CACHE_HITS: &str = "lumos_cache_hits_total";
+const CACHE_MISSES: &str = "lumos_cache_misses_total";
+const CANCELLED: &str = "lumos_cancelled_total";
+
+/// Name, type and help of every exported metric
+const FAMILIES: [(&str, &str, &str); 10] = [
+    (
+        REQUESTS,
+        "counter",
+        "Requests by the model that served them, or was asked for if none did",
+    ),
+    (
+        ERRORS,
+        "counter",
+        "Failed upstream attempts by kind and status",
+    ),
+    (RETRIES, "counter", "Attempts retried on the same model"),
+    (TOKENS, "counter", "Tokens reported by the upstream usage"),
+    (STREAMS, "gauge", "Responses currently streaming"),
+    (
+        FIRST_TOKEN,
+        "histogram",
+        "Seconds from the request to the first streamed token",
+    ),
+    (
+        DURATION,
+        "histogram",
+        "Seconds from the request to the end of its response",
+    ),
+    (CACHE_HITS, "counter", "Requests answered from the cache"),
+    (
+        CACHE_MISSES,
+        "counter",
+        "Cacheable requests not found in the cache",
+    ),
+    (
+        CANCELLED,
+        "counter",
+        "Requests dropped by the client before the end of the response",
+    ),
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Default)]
+struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (count, bound) in self.counts.iter_mut().zip(BUCKETS) {
+            if value <= bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    values: BTreeMap<(&'static str, Labels), f64>,
+    histograms: BTreeMap<(&'static str, Labels), Histogram>,
+}
+
+impl Registry {
+    fn add(&mut self, name: &'static str, labels: Labels, value: f64) {
+        *self.values.entry((name, labels)).or_default() += value;
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+/// Counts a response as streaming until dropped
+pub struct StreamGauge {
+    registry: Arc<Mutex<Registry>>,
+    labels: Labels,
+}
+
+impl Drop for StreamGauge {
+    fn drop(&mut self) {
+        let mut registry = self.registry.lock().unwrap();
+        registry.add(STREAMS, std::mem::take(&mut self.labels), -1.0);
+    }
+}
+
+fn labels(model: &str, provider: &str) -> Labels {
+    vec![
+        ("model", model.to_string()),
+        ("provider", provider.to_string()),
+    ]
+}
+
+impl Metrics {
+    pub fn request(&self, model: &str, provider: &str) {
+        let mut registry = self.registry.lock().unwrap();
+        registry.add(REQUESTS, labels(model, provider), 1.0);
+    }
+
+    /// A failed attempt, `kind` is e.g. `connect` or `status`
+    pub fn error(&self, model: &str, provider: &str, kind: &str, status: Option<u16>) {
+        let mut labels = labels(model, provider);
+        labels.push(("kind", kind.to_string()));
+        labels.push(("status", status.map(|s| s.to_string()).unwrap_or_default()));
+        self.registry.lock().unwrap().add(ERRORS, labels, 1.0);
+    }
+
+    pub fn retry(&self, model: &str, provider: &str) {
+        let mut registry = self.registry.lock().unwrap();
+        registry.add(RETRIES, labels(model, provider), 1.0);
+    }
+
+    pub fn tokens(&self, model: &str, provider: &str, input: u64, output: u64) {
+        let mut registry = self.registry.lock().unwrap();
+        for (direction, tokens) in [("input", input), ("output", output)] {
+            let mut labels = labels(model, provider);
+            labels.push(("direction", direction.to_string()));
+            registry.add(TOKENS, labels, tokens as f64);
+        }
+    }
+
+    pub fn first_token(&self, model: &str, provider: &str, elapsed: Duration) {
+        let mut registry = self.registry.lock().unwrap();
+        registry
+            .histograms
+            .entry((FIRST_TOKEN, labels(model, provider)))
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn duration(&self, model: &str, provider: &str, elapsed: Duration) {
+        let mut registry = self.registry.lock().unwrap();
+        registry
+            .histograms
+            .entry((DURATION, labels(model, provider)))
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// A lookup of a cacheable request, labelled with the requested model only
+    pub fn cache(&self, model: &str, hit: bool) {
+        let name = if hit { CACHE_HITS } else { CACHE_MISSES };
+        let labels = vec![("model", model.to_string())];
+        self.registry.lock().unwrap().add(name, labels, 1.0);
+    }
+
+    /// A request the client went away from, labelled with the requested model only
+    pub fn cancelled(&self, model: &str) {
+        let labels = vec![("model", model.to_string())];
+        self.registry.lock().unwrap().add(CANCELLED, labels, 1.0);
+    }
+
+    pub fn stream(&self, model: &str, provider: &str) -> StreamGauge {
+        let labels = labels(model, provider);
+        self.registry
+            .lock()
+            .unwrap()
+            .add(STREAMS, labels.clone(), 1.0);
+        StreamGauge {
+            registry: self.registry.clone(),
+            labels,
+        }
+    }
+
+    /// Every metric in the Prometheus text format
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        for (family, kind, help) in FAMILIES {
+            let _ = writeln!(out, "# HELP {} {}", family, help);
+            let _ = writeln!(out, "# TYPE {} {}", family, kind);
+
+            for ((name, labels), value) in &registry.values {
+                if *name == family {
+                    let _ = writeln!(out, "{}{} {}", family, format_labels(labels, None), value);
+                }
+            }
+            for ((name, labels), histogram) in &registry.histograms {
+                if *name != family {
+                    continue;
+                }
+                for (count, bound) in histogram.counts.iter().zip(BUCKETS) {
+                    let le = bound.to_string();
+                    let _ = writeln!(
+                        out,
+                        "{}_bucket{} {}",
+                        family,
+                        format_labels(labels, Some(&le)),
+                        count
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    family,
+                    format_labels(labels, Some("+Inf")),
+                    histogram.count
+                );
+                let labels = format_labels(labels, None);
+                let _ = writeln!(out, "{}_sum{} {}", family, labels, histogram.sum);
+                let _ = writeln!(out, "{}_count{} {}", family, labels, histogram.count);
+            }
+        }
+
+        out
+    }
+}
+
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<_>>();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    format!("{{{}}}", pairs.join(","))
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
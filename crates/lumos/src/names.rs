@@ -0,0 +1,53 @@
+/// Model names as sent by Ollama clients, mapped to the aliases of the config.
+/// A requested name matches an alias, one of the `aliases` of its model, or
+/// the Ollama name of the alias, ignoring case and a `:latest` tag. The Ollama
+/// name of an alias without a tag turns its first `-` into `:`, so `glm-4-plus`
+/// is listed as `glm:4-plus`, an alias with a tag like `qwen2.5:72b` is kept.
+use std::collections::HashMap;
+
+use crate::structs::config::Model;
+
+const LATEST: &str = ":latest";
+
+/// Lowercase name without `:latest`, names with the same
+/// normalised form can't be told apart by clients
+pub fn normalize(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    name.strip_suffix(LATEST).map(String::from).unwrap_or(name)
+}
+
+/// The name of the alias in `/api/tags` and in the responses
+pub fn ollama_name(alias: &str) -> String {
+    if alias.contains(':') {
+        return alias.to_string();
+    }
+    alias.replacen('-', ":", 1)
+}
+
+/// Every name that resolves to the model, the alias first
+pub fn names<'a>(alias: &'a str, model: &'a Model) -> Vec<String> {
+    let mut names = vec![alias.to_string(), ollama_name(alias)];
+    names.extend(model.aliases.iter().cloned());
+    names.dedup();
+    names
+}
+
+/// The alias of the model a client asks for, an exact alias wins,
+/// then the first alias in order with a matching name
+pub fn resolve<'a>(models: &'a HashMap<String, Model>, requested: &str) -> Option<&'a str> {
+    if let Some((alias, _)) = models.get_key_value(requested) {
+        return Some(alias);
+    }
+
+    let requested = normalize(requested);
+    let mut aliases = models.iter().collect::<Vec<_>>();
+    aliases.sort_by_key(|(alias, _)| *alias);
+    aliases
+        .into_iter()
+        .find(|(alias, model)| {
+            names(alias, model)
+                .iter()
+                .any(|name| normalize(name) == requested)
+        })
+        .map(|(alias, _)| alias.as_str())
+}
@@ -0,0 +1,213 @@
+/// Spread the requests of a model over its upstream endpoints.
+/// Endpoints failing `max_failures` times in a row are ejected for `eject_secs`,
+/// and endpoints with a health check are ejected and re-admitted by its result.
+use futures_util::future::join_all;
+use reqwest::{Client, Url};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::structs::app::AppState;
+use crate::structs::config::{Balance, BalanceStrategy, Model};
+
+#[derive(Default)]
+struct EndpointState {
+    in_flight: usize,
+    failures: u32,
+    ejected_until: Option<Instant>,
+    /// Smooth weighted round-robin counter
+    current_weight: i64,
+    last_checked: Option<Instant>,
+}
+
+type Endpoints = Arc<Mutex<HashMap<String, EndpointState>>>;
+
+#[derive(Default)]
+pub struct Balancer {
+    endpoints: Endpoints,
+}
+
+/// Counts a request as in flight on its endpoint until dropped
+pub struct InFlight {
+    endpoints: Endpoints,
+    url: String,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Some(state) = self.endpoints.lock().unwrap().get_mut(&self.url) {
+            state.in_flight = state.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+impl Balancer {
+    /// Pick the endpoint for the next request of the model. If every endpoint
+    /// is ejected all of them are considered rather than failing the request.
+    pub fn pick(&self, model: &Model) -> (String, InFlight) {
+        let now = Instant::now();
+        let candidates = model.endpoints();
+        let mut endpoints = self.endpoints.lock().unwrap();
+
+        let ejected = |url: &str| {
+            endpoints
+                .get(url)
+                .and_then(|state| state.ejected_until)
+                .is_some_and(|until| until > now)
+        };
+        let mut available = candidates
+            .iter()
+            .filter(|endpoint| !ejected(&endpoint.url))
+            .collect::<Vec<_>>();
+        if available.is_empty() {
+            available = candidates.iter().collect();
+        }
+
+        let url = match model.balance.strategy {
+            BalanceStrategy::WeightedRoundRobin => {
+                let total = available.iter().map(|e| e.weight as i64).sum::<i64>();
+                let mut best: Option<(&str, i64)> = None;
+                for endpoint in &available {
+                    let state = endpoints.entry(endpoint.url.clone()).or_default();
+                    state.current_weight += endpoint.weight as i64;
+                    if best.is_none_or(|(_, weight)| state.current_weight > weight) {
+                        best = Some((&endpoint.url, state.current_weight));
+                    }
+                }
+                let url = best.map(|(url, _)| url.to_string()).unwrap_or_default();
+                if let Some(state) = endpoints.get_mut(&url) {
+                    state.current_weight -= total;
+                }
+                url
+            }
+            BalanceStrategy::LeastInFlight => available
+                .iter()
+                .min_by(|a, b| {
+                    let load = |url: &str| endpoints.get(url).map_or(0, |s| s.in_flight);
+                    // in_flight / weight, compared without division
+                    (load(&a.url) * b.weight as usize).cmp(&(load(&b.url) * a.weight as usize))
+                })
+                .map(|endpoint| endpoint.url.clone())
+                .unwrap_or_default(),
+        };
+
+        endpoints.entry(url.clone()).or_default().in_flight += 1;
+        let in_flight = InFlight {
+            endpoints: self.endpoints.clone(),
+            url: url.clone(),
+        };
+        (url, in_flight)
+    }
+
+    /// Record whether the endpoint answered, failures are connect errors and 5xx
+    pub fn report(&self, model: &Model, url: &str, failed: bool) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let state = endpoints.entry(url.to_string()).or_default();
+
+        if !failed {
+            state.failures = 0;
+            return;
+        }
+
+        state.failures += 1;
+        if state.failures >= model.balance.max_failures {
+            let eject = Duration::from_secs(model.balance.eject_secs);
+            state.ejected_until = Some(Instant::now() + eject);
+            warn!(
+                "Endpoint {} ejected for {:?} after {} failures",
+                url, eject, state.failures
+            );
+        }
+    }
+
+    /// Probe the endpoints of every model with a health check that is due
+    pub async fn check_health(&self, config: &Config) {
+        let client = Client::new();
+        let now = Instant::now();
+
+        let mut due = Vec::new();
+        {
+            let mut endpoints = self.endpoints.lock().unwrap();
+            for model in config.models().values() {
+                let Some(path) = &model.balance.health_check_path else {
+                    continue;
+                };
+                let interval = Duration::from_secs(model.balance.health_check_secs);
+                for endpoint in model.endpoints() {
+                    let state = endpoints.entry(endpoint.url.clone()).or_default();
+                    if state.last_checked.is_some_and(|at| now - at < interval) {
+                        continue;
+                    }
+                    state.last_checked = Some(now);
+                    due.push((endpoint.url, path, interval));
+                }
+            }
+        }
+
+        // a slow endpoint doesn't hold up the checks of the others
+        let probes = due.into_iter().map(|(url, path, interval)| {
+            let client = &client;
+            async move {
+                let healthy = match Url::parse(&url).and_then(|base| base.join(path)) {
+                    Ok(probe) => client
+                        .get(probe)
+                        .timeout(interval)
+                        .send()
+                        .await
+                        .is_ok_and(|response| !response.status().is_server_error()),
+                    Err(_) => false,
+                };
+                (url, interval, healthy)
+            }
+        });
+        let results = join_all(probes).await;
+
+        let mut endpoints = self.endpoints.lock().unwrap();
+        for (url, interval, healthy) in results {
+            let state = endpoints.entry(url.clone()).or_default();
+            let was_ejected = state.ejected_until.is_some_and(|until| until > now);
+            if healthy {
+                state.failures = 0;
+                state.ejected_until = None;
+                if was_ejected {
+                    info!("Endpoint {} is healthy again", url);
+                }
+            } else {
+                // stays ejected until the next check passes
+                state.ejected_until = Some(now + interval * 2);
+                if !was_ejected {
+                    warn!("Endpoint {} failed its health check", url);
+                }
+            }
+        }
+    }
+}
+
+/// How often the config is read again for health checks: the shortest
+/// `health_check_secs`, the default one if no model has a health check
+fn check_interval(config: &Config) -> Duration {
+    let secs = config
+        .models()
+        .values()
+        .filter(|model| model.balance.health_check_path.is_some())
+        .map(|model| model.balance.health_check_secs)
+        .min()
+        .unwrap_or(Balance::default().health_check_secs);
+    Duration::from_secs(secs.max(1))
+}
+
+/// Run the health checks of the balanced endpoints in the background
+pub async fn run_health_checks(state: Arc<AppState>) {
+    loop {
+        let interval = match Config::from_file(&state.config_path) {
+            Ok(config) => {
+                state.balancer.check_health(&config).await;
+                check_interval(&config)
+            }
+            Err(_) => Duration::from_secs(Balance::default().health_check_secs),
+        };
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,177 @@
+/// Cache of the responses to deterministic requests.
+/// With `[cache] enabled = true` the streamed answer to a chat or generate
+/// request at temperature 0 is kept, keyed on the model alias, the messages and
+/// the options of the request, and an identical request is answered from it
+/// without going upstream. Entries live in memory up to `max_entries`, or in
+/// one file per entry in `dir`.
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+use crate::ollama::cassette::fnv1a;
+use crate::ollama::options;
+use crate::structs::config::{CacheConfig, Model};
+use crate::structs::ollama::ChatOptions;
+use crate::structs::openai::Usage;
+
+/// A completed response, as streamed by the upstream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    /// The normalised request, compared on lookup
+    pub key: String,
+    /// Alias of the model that served the request
+    pub served_by: String,
+    /// Contents of the streamed chunks, in order
+    pub chunks: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+/// Key of a request, `None` if it isn't deterministic and can't be cached.
+/// Messages and options are taken as sent upstream, with the system prompt
+/// and the default options of the model, so that requests spelling them out
+/// share the entry of those relying on the defaults.
+pub fn key(
+    alias: &str,
+    provider: &Model,
+    messages: &[Value],
+    options: &ChatOptions,
+) -> Option<String> {
+    let mut effective = json!({});
+    options::apply(&mut effective, &provider.options, options);
+    if effective["temperature"].as_f64() != Some(0.0) {
+        return None;
+    }
+    if let Some(num_ctx) = options.num_ctx {
+        effective["num_ctx"] = json!(num_ctx);
+    }
+    let messages = options::with_system_prompt(provider, messages);
+    // object keys are sorted, so equal options always give the same key
+    Some(json!({ "model": alias, "messages": messages, "options": effective }).to_string())
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<String, (u64, Arc<CachedResponse>)>,
+    /// Keys by last use, the oldest first
+    order: BTreeMap<u64, String>,
+    clock: u64,
+}
+
+impl Lru {
+    fn get(&mut self, key: &str) -> Option<Arc<CachedResponse>> {
+        self.clock += 1;
+        let (used, entry) = self.entries.get_mut(key)?;
+        self.order.remove(used);
+        *used = self.clock;
+        self.order.insert(self.clock, key.to_string());
+        Some(entry.clone())
+    }
+
+    fn insert(&mut self, entry: CachedResponse, max_entries: usize) {
+        self.clock += 1;
+        let key = entry.key.clone();
+        if let Some((used, _)) = self
+            .entries
+            .insert(key.clone(), (self.clock, Arc::new(entry)))
+        {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.clock, key);
+        while self.entries.len() > max_entries {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ResponseCache {
+    memory: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResponseCache {
+    /// The entry of the key, counted as a hit or a miss
+    pub fn get(&self, config: &CacheConfig, key: &str) -> Option<Arc<CachedResponse>> {
+        let entry = match &config.dir {
+            Some(dir) => fs::read_to_string(file(dir, key))
+                .ok()
+                .and_then(|contents| serde_json::from_str::<CachedResponse>(&contents).ok())
+                .filter(|entry| entry.key == key)
+                .map(Arc::new),
+            None => self.memory.lock().unwrap().get(key),
+        };
+        let counter = if entry.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        entry
+    }
+
+    pub fn insert(&self, config: &CacheConfig, entry: CachedResponse) {
+        let Some(dir) = &config.dir else {
+            let mut memory = self.memory.lock().unwrap();
+            memory.insert(entry, config.max_entries);
+            return;
+        };
+        let path = file(dir, &entry.key);
+        let result = fs::create_dir_all(dir)
+            .and_then(|_| fs::write(&path, serde_json::to_string(&entry).unwrap_or_default()));
+        if let Err(e) = result {
+            warn!("Failed to write cache entry {}: {}", path.display(), e);
+        }
+    }
+
+    /// Drop every entry and return how many there were
+    pub fn purge(&self, config: &CacheConfig) -> usize {
+        let mut memory = self.memory.lock().unwrap();
+        let mut purged = memory.entries.len();
+        *memory = Lru::default();
+        if let Some(dir) = &config.dir {
+            for path in entry_files(dir) {
+                if fs::remove_file(&path).is_ok() {
+                    purged += 1;
+                }
+            }
+        }
+        purged
+    }
+
+    /// Hits, misses and entries of the cache
+    pub fn stats(&self, config: &CacheConfig) -> Value {
+        let entries = match &config.dir {
+            Some(dir) => entry_files(dir).len(),
+            None => self.memory.lock().unwrap().entries.len(),
+        };
+        json!({
+            "enabled": config.enabled,
+            "hits": self.hits.load(Ordering::Relaxed),
+            "misses": self.misses.load(Ordering::Relaxed),
+            "entries": entries,
+        })
+    }
+}
+
+fn file(dir: &str, key: &str) -> PathBuf {
+    Path::new(dir).join(format!("{:016x}.json", fnv1a(key.as_bytes())))
+}
+
+fn entry_files(dir: &str) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect()
+}
@@ -0,0 +1,173 @@
+/// Record-and-replay of raw upstream responses.
+/// A model with `cassettes = "<dir>"` saves every upstream response to a
+/// cassette file named after the model and a hash of the request body. With
+/// `provider = "replay"` the same file is served instead, byte for byte.
+use anyhow::Result;
+use bytes::Bytes;
+use futures_util::stream::{self, Stream, StreamExt};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use tracing::{info, warn};
+
+use crate::ollama::upstream::UpstreamError;
+use crate::structs::config::Model;
+
+/// Body of an upstream response
+pub type UpstreamBody = Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Cassette {
+    request: Value,
+    status: u16,
+    body: String,
+}
+
+impl Cassette {
+    fn ok(request: &Value, raw: &[u8]) -> Self {
+        Cassette {
+            request: request.clone(),
+            status: StatusCode::OK.as_u16(),
+            body: String::from_utf8_lossy(raw).into_owned(),
+        }
+    }
+}
+
+/// File of the cassette of a request. `stream_options` is left out of the
+/// hash, so that `replay` finds what a provider asked for the usage recorded.
+pub fn path(dir: &str, provider: &Model, request_body: &Value) -> PathBuf {
+    let name = provider
+        .model_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let mut request = request_body.clone();
+    if let Some(fields) = request.as_object_mut() {
+        fields.remove("stream_options");
+    }
+    let key = fnv1a(request.to_string().as_bytes());
+    Path::new(dir).join(format!("{}-{:016x}.json", name, key))
+}
+
+/// Serve the recorded response of the request, split into SSE events
+pub fn replay(provider: &Model, request_body: &Value) -> Result<UpstreamBody, UpstreamError> {
+    let Some(dir) = &provider.cassettes else {
+        return Err(missing(format!(
+            "model {} has provider replay but no cassettes",
+            provider.model_name
+        )));
+    };
+    let path = path(dir, provider, request_body);
+    let cassette = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<Cassette>(&contents).ok())
+        .ok_or_else(|| missing(format!("no cassette {}, record it first", path.display())))?;
+
+    let status = StatusCode::from_u16(cassette.status).unwrap_or(StatusCode::BAD_GATEWAY);
+    if !status.is_success() {
+        return Err(UpstreamError::Status {
+            status,
+            message: cassette.body,
+            retry_after: None,
+        });
+    }
+
+    let events = cassette
+        .body
+        .split_inclusive("\n\n")
+        .map(|event| Ok(Bytes::from(event.to_string())))
+        .collect::<Vec<_>>();
+    Ok(Box::pin(stream::iter(events)))
+}
+
+/// Save the response once its body has been read to the end
+pub fn record(
+    dir: &str,
+    provider: &Model,
+    request_body: &Value,
+    result: Result<UpstreamBody, UpstreamError>,
+) -> Result<UpstreamBody, UpstreamError> {
+    let path = path(dir, provider, request_body);
+    let request = request_body.clone();
+
+    let mut body = match result {
+        Ok(body) => body,
+        Err(UpstreamError::Status {
+            status,
+            message,
+            retry_after,
+        }) => {
+            save(
+                &path,
+                &Cassette {
+                    request,
+                    status: status.as_u16(),
+                    body: message.clone(),
+                },
+            );
+            return Err(UpstreamError::Status {
+                status,
+                message,
+                retry_after,
+            });
+        }
+        Err(e) => return Err(e),
+    };
+
+    // dispatch stops reading at `[DONE]`, the cassette is saved there
+    // rather than at the end of the body
+    let recorded = async_stream::try_stream! {
+        let mut raw = Vec::new();
+        let mut saved = false;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            raw.extend_from_slice(&chunk);
+            if raw.trim_ascii_end().ends_with(b"data: [DONE]") {
+                save(&path, &Cassette::ok(&request, &raw));
+                saved = true;
+            }
+            yield chunk;
+        }
+        if !saved {
+            save(&path, &Cassette::ok(&request, &raw));
+        }
+    };
+    Ok(Box::pin(recorded))
+}
+
+fn save(path: &Path, cassette: &Cassette) {
+    let write = || -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(cassette)?)?;
+        Ok(())
+    };
+    match write() {
+        Ok(()) => info!("Recorded cassette {}", path.display()),
+        Err(e) => warn!("Failed to record cassette {}: {}", path.display(), e),
+    }
+}
+
+fn missing(message: String) -> UpstreamError {
+    UpstreamError::Status {
+        status: StatusCode::NOT_FOUND,
+        message,
+        retry_after: None,
+    }
+}
+
+/// FNV-1a, stable across builds unlike the std hasher
+pub(super) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -1,45 +1,43 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use axum::{
-    extract::{Json, State},
+    extract::{Extension, Json, State},
     response::IntoResponse,
 };
 use std::sync::Arc;
 
-use crate::config::Config;
-use crate::ollama::dispatch;
+use crate::auth::Client;
+use crate::error::ApiError;
+use crate::ollama::dispatch::{log_rejected, served_model};
+use crate::ollama::{dispatch, Dispatch};
 use crate::structs::app::AppState;
 use crate::structs::ollama::ChatRequest;
 use crate::structs::ollama::ChatType;
 
 pub async fn handler(
     State(state): State<Arc<AppState>>,
+    client: Option<Extension<Client>>,
     Json(request): Json<ChatRequest>,
-) -> Result<impl IntoResponse, (axum::http::StatusCode, String)> {
-    chat(State(state), Json(request))
-        .await
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+) -> Result<impl IntoResponse, ApiError> {
+    chat(
+        State(state),
+        client.map(|Extension(client)| client),
+        Json(request),
+    )
+    .await
+    .map_err(ApiError::from)
 }
 
 async fn chat(
     State(state): State<Arc<AppState>>,
+    client: Option<Client>,
     Json(req): Json<ChatRequest>,
 ) -> Result<impl IntoResponse, anyhow::Error> {
-    let model = &req.model.replacen(":", "-", 1); // deepseek:chat -> deepseek-chat
-
-    // check model name if match in app state
-    if model != &state.model_name {
-        return Err(anyhow::anyhow!(
-            "Model name not match in app state:{} != {}",
-            model,
-            state.model_name
-        ));
-    }
-
-    let config_path = &state.config_path;
-
-    let config = Config::from_file(config_path).context("Failed to load config")?;
-    let provider = config.get_model(model).context("Provider not found")?;
+    let (config, model) = served_model(&state, &req.model)
+        .inspect_err(|e| log_rejected(client.as_ref(), &req.model, e))?;
 
     // Dispatch the request to the provider service and get the stream
-    dispatch(model, req.messages, provider, ChatType::Chat).await
+    let ctx = Dispatch::new(state.clone(), &config, model, client)
+        .with_request(&req)
+        .with_options(req.options.clone().unwrap_or_default());
+    dispatch(ctx, req.messages, ChatType::Chat, None).await
 }
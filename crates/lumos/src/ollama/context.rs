@@ -0,0 +1,95 @@
+/// Conversation memory of `/api/generate`.
+/// Upstreams are chat only and have no token context, so the turn history is kept
+/// here and the client gets an opaque handle in the `context` field instead.
+/// At most `MAX_CONTEXTS` conversations are kept, the least recently used go first.
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::structs::ollama::Message;
+
+/// How long a conversation is kept after its last turn
+const CONTEXT_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Conversations kept at most
+pub const MAX_CONTEXTS: usize = 1000;
+
+struct Entry {
+    expires_at: Instant,
+    /// `clock` when last used
+    used: u64,
+    messages: Vec<Message>,
+}
+
+#[derive(Default)]
+struct Entries {
+    entries: HashMap<Vec<u32>, Entry>,
+    clock: u64,
+}
+
+pub struct ContextStore {
+    entries: Mutex<Entries>,
+    max_entries: usize,
+}
+
+impl Default for ContextStore {
+    fn default() -> Self {
+        ContextStore::new(MAX_CONTEXTS)
+    }
+}
+
+impl ContextStore {
+    pub fn new(max_entries: usize) -> Self {
+        ContextStore {
+            entries: Mutex::default(),
+            max_entries,
+        }
+    }
+
+    /// Turn history of a handle, empty if the handle is unknown or expired
+    pub fn get(&self, handle: &[u32]) -> Vec<Message> {
+        let mut store = self.entries.lock().unwrap();
+        store.clock += 1;
+        let clock = store.clock;
+        match store.entries.get_mut(handle) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                entry.used = clock;
+                entry.messages.clone()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Store the turn history and return a new handle for it
+    pub fn insert(&self, messages: Vec<Message>) -> Vec<u32> {
+        let handle: Vec<u32> = rand::thread_rng().gen::<[u32; 4]>().to_vec();
+
+        let now = Instant::now();
+        let mut store = self.entries.lock().unwrap();
+        store.clock += 1;
+        let clock = store.clock;
+        let entries = &mut store.entries;
+        entries.retain(|_, entry| entry.expires_at > now);
+        while entries.len() >= self.max_entries.max(1) {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(handle, _)| handle.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            handle.clone(),
+            Entry {
+                expires_at: now + CONTEXT_TTL,
+                used: clock,
+                messages,
+            },
+        );
+
+        handle
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_stream::try_stream;
 use axum::{
     body::Body,
@@ -6,37 +6,112 @@ use axum::{
 };
 use bytes::BytesMut;
 use chrono::Utc;
-use futures_util::stream::{Stream, StreamExt};
-use reqwest::Client;
+use futures_util::stream::{self, Stream, StreamExt};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
 use serde_json::{json, Value};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
 
-use crate::structs::config::Model;
-use crate::structs::ollama::{ChatType, Message};
+use crate::auth::Client as AuthClient;
+use crate::config::Config;
+use crate::costs::ANONYMOUS;
+use crate::error;
+use crate::logging::{self, REQUEST_TARGET};
+use crate::names;
+use crate::ollama::balancer::InFlight;
+use crate::ollama::cache::{self, CachedResponse};
+use crate::ollama::cassette::{self, UpstreamBody};
+use crate::ollama::mock;
+use crate::ollama::options;
+use crate::ollama::timeouts;
+use crate::ollama::truncate;
+use crate::ollama::upstream::{parse_retry_after, Timeout, UpstreamError};
+use crate::structs::app::AppState;
+use crate::structs::config::{Model, Pricing, ProviderName};
+use crate::structs::ollama::{ChatOptions, ChatType, Message};
+use crate::structs::openai::{EmbeddingResponse, Usage};
 
-pub async fn dispatch(
-    model: &str,
-    messages: Vec<Message>,
-    provider: &Model,
-    chat_type: ChatType,
-) -> Result<impl IntoResponse, anyhow::Error> {
-    let stream = send(model.to_string(), messages, provider, chat_type).await?;
-    let body = Body::from_stream(stream);
-    let response = Response::builder()
-        .header("Content-Type", "text/plain")
-        .body(body)
-        .unwrap();
-    Ok(response)
+/// Called with the full response text once the upstream is done,
+/// the returned value is sent as `context` in the final chunk
+pub type OnDone = Box<dyn FnOnce(&str) -> Value + Send>;
+
+/// Provider logged for the requests answered from the cache
+const CACHE_PROVIDER: &str = "cache";
+
+/// A request on its way to the upstream
+pub struct Dispatch<'a> {
+    pub state: Arc<AppState>,
+    pub config: &'a Config,
+    /// Alias of the requested model
+    pub model: String,
+    /// The authenticated client, if authentication is on
+    pub client: Option<AuthClient>,
+    /// Identifies the request in the logs
+    pub request_id: String,
+    /// The request as received, for the transcript
+    pub request: Value,
+    /// Sampling options of the request
+    pub options: ChatOptions,
+    /// When the request came in, `total_secs` spans its retries and fallbacks
+    pub started: Instant,
 }
 
-async fn send(
-    model: String,
+impl<'a> Dispatch<'a> {
+    pub fn new(
+        state: Arc<AppState>,
+        config: &'a Config,
+        model: String,
+        client: Option<AuthClient>,
+    ) -> Self {
+        Dispatch {
+            state,
+            config,
+            model,
+            client,
+            request_id: logging::request_id(),
+            request: Value::Null,
+            options: ChatOptions::default(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ChatOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Keep the incoming request for the transcript
+    pub fn with_request(mut self, request: &impl Serialize) -> Self {
+        self.request = serde_json::to_value(request).unwrap_or_default();
+        self
+    }
+
+    /// Key of the request in the cache, if it is on and the request is deterministic
+    fn cache_key(&self, messages: &[Value]) -> Option<String> {
+        if !self.config.cache.enabled {
+            return None;
+        }
+        let provider = self.config.get_model(&self.model)?;
+        cache::key(&self.model, provider, messages, &self.options)
+    }
+
+    fn records_transcript(&self) -> bool {
+        self.config
+            .get_model(&self.model)
+            .is_some_and(|model| model.transcript)
+    }
+}
+
+pub async fn dispatch(
+    ctx: Dispatch<'_>,
     messages: Vec<Message>,
-    provider: &Model,
     chat_type: ChatType,
-) -> Result<impl Stream<Item = Result<String, anyhow::Error>> + Unpin + Send, anyhow::Error> {
-    let api_key = &provider.api_key;
+    on_done: Option<OnDone>,
+) -> Result<impl IntoResponse, anyhow::Error> {
     let messages = messages
         .into_iter()
         .map(|msg| {
@@ -46,93 +121,801 @@ async fn send(
             })
         })
         .collect::<Vec<_>>();
+    let prompt = messages
+        .last()
+        .and_then(|msg| msg["content"].as_str())
+        .map(logging::content);
 
-    let client = Client::new();
+    let log = RequestLog::new(&ctx, prompt);
+    let cache_key = ctx.cache_key(&messages);
+    if let Some(key) = &cache_key {
+        let cached = ctx.state.cache.get(&ctx.config.cache, key);
+        ctx.state.metrics.cache(&ctx.model, cached.is_some());
+        if let Some(cached) = cached {
+            log.completed(
+                None,
+                &cached.served_by,
+                CACHE_PROVIDER,
+                None,
+                cached.usage.as_ref(),
+            );
+            let stream = replay_cached(&ctx, &cached, chat_type, on_done);
+            return Ok(into_response(stream));
+        }
+    }
 
-    let request_body = json!({
-        "model": model,
-        "messages": messages,
-        "stream": true
-    });
+    let response = connect_with_fallback(&ctx, |provider| {
+        let mut request_body = json!({
+            "model": provider.model_name,
+            "messages": truncate::fit(
+                provider,
+                &ctx.options,
+                options::with_system_prompt(provider, &messages),
+            ),
+            "stream": true,
+        });
+        ask_for_usage(provider, &mut request_body);
+        options::apply(&mut request_body, &provider.options, &ctx.options);
+        Some((Target::Balanced, request_body))
+    })
+    .await
+    .inspect_err(|e| log.failed(e))?;
 
-    // 将模型名称中的 "-" 替换为 ":"
-    let model = model.replacen('-', ":", 1);
+    let stream = send(&ctx, response, log, chat_type, on_done, cache_key);
+    Ok(into_response(stream))
+}
+
+/// Answer from the cache, in the chunks the upstream streamed the response in
+fn replay_cached(
+    ctx: &Dispatch<'_>,
+    cached: &CachedResponse,
+    chat_type: ChatType,
+    on_done: Option<OnDone>,
+) -> impl Stream<Item = Result<String, anyhow::Error>> + Send + 'static {
+    let model = names::ollama_name(&ctx.model);
+    let context = match on_done {
+        Some(on_done) => on_done(&cached.chunks.concat()),
+        None => json!([1, 2, 3]),
+    };
+
+    let mut lines = cached
+        .chunks
+        .iter()
+        .map(|content| chunk(&model, &chat_type, content))
+        .collect::<Vec<_>>();
+    lines.push(done_chunk(&model, context, cached.usage.as_ref()));
+    stream::iter(lines.into_iter().map(|line| Ok(format!("{}\n", line))))
+}
+
+/// Prompt of a text completion request, built for each target
+/// since prompt formats like FIM templates are model specific
+pub struct Completion {
+    pub prompt: String,
+    pub suffix: Option<String>,
+    pub stop: Vec<String>,
+}
+
+/// Send a raw prompt to the text completion endpoint of the provider,
+/// with `suffix` set the upstream fills in the text between prompt and suffix.
+/// Targets without `completion_url` or for which `build` returns `None` are skipped.
+pub async fn complete(
+    ctx: Dispatch<'_>,
+    build: impl Fn(&Model) -> Option<Completion>,
+) -> Result<impl IntoResponse, anyhow::Error> {
+    let log = RequestLog::new(&ctx, None);
+    let response = connect_with_fallback(&ctx, |provider| {
+        let url = provider.completion_url.clone()?;
+        let completion = build(provider)?;
+
+        let mut request_body = json!({
+            "model": provider.model_name,
+            "prompt": completion.prompt,
+            "stream": true,
+        });
+        ask_for_usage(provider, &mut request_body);
+        if let Some(suffix) = completion.suffix {
+            request_body["suffix"] = json!(suffix);
+        }
+        if !completion.stop.is_empty() {
+            request_body["stop"] = json!(completion.stop);
+        }
+        options::apply(&mut request_body, &provider.options, &ctx.options);
+        Some((Target::Fixed(url), request_body))
+    })
+    .await
+    .inspect_err(|e| log.failed(e))?;
+
+    let stream = send(&ctx, response, log, ChatType::Generate, None, None);
+    Ok(into_response(stream))
+}
+
+fn into_response(
+    stream: impl Stream<Item = Result<String, anyhow::Error>> + Send + 'static,
+) -> Response {
+    let body = Body::from_stream(stream);
+    Response::builder()
+        .header("Content-Type", "text/plain")
+        .body(body)
+        .unwrap()
+}
+
+/// Have the usage sent as the last chunk of the stream, if the provider can
+fn ask_for_usage(provider: &Model, request_body: &mut Value) {
+    if provider.provider.streams_usage() {
+        request_body["stream_options"] = json!({ "include_usage": true });
+    }
+}
+
+/// Upstream url of a request
+enum Target {
+    /// `url` or one of the balanced `endpoints` of the model
+    Balanced,
+    /// A url of its own like `completion_url`
+    Fixed(String),
+}
+
+/// An upstream response, counted in flight on its endpoint until dropped
+struct Connection {
+    body: UpstreamBody,
+    /// Status of the upstream response, `None` for `mock` and `replay`
+    status: Option<StatusCode>,
+    /// Alias of the model that accepted the request
+    served_by: String,
+    /// The body sent upstream
+    request: Value,
+    provider: String,
+    pricing: Option<Pricing>,
+    _in_flight: Option<InFlight>,
+}
+
+/// Try the model and then its fallbacks in order until one accepts the request.
+/// `request` builds the target and body for a model, or `None` if it can't serve it.
+async fn connect_with_fallback(
+    ctx: &Dispatch<'_>,
+    request: impl Fn(&Model) -> Option<(Target, Value)>,
+) -> Result<Connection, anyhow::Error> {
+    let state = &ctx.state;
+    let model = &ctx.model;
+    let mut last_error = None;
+
+    for (alias, provider) in ctx.config.fallback_chain(model) {
+        let Some((target, request_body)) = request(provider) else {
+            continue;
+        };
+
+        let mut attempt = 1;
+        let error = loop {
+            let connection =
+                connect_target(state, alias, provider, &target, &request_body, ctx.started);
+            match connection.await {
+                Ok(response) => {
+                    info!(
+                        "{} served by {} ({}/{}) after {} attempt(s)",
+                        model, alias, provider.provider, provider.model_name, attempt
+                    );
+                    return Ok(response);
+                }
+                Err(e) => {
+                    let delay = provider
+                        .retry
+                        .as_ref()
+                        .and_then(|policy| e.retry_delay(policy, attempt));
+                    let Some(delay) = delay else {
+                        break e;
+                    };
+                    let provider_name = provider.provider.to_string();
+                    state.metrics.retry(alias, &provider_name);
+                    warn!(
+                        "{} attempt {} on {} failed: {}, retrying in {:?}",
+                        model, attempt, alias, e, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        };
+
+        if !error.is_retryable() {
+            return Err(error.into());
+        }
+        warn!(
+            "{} failed on {} after {} attempt(s): {}",
+            model, alias, attempt, error
+        );
+        last_error = Some(error);
+    }
+
+    match last_error {
+        Some(e) => Err(e.into()),
+        None => Err(anyhow::anyhow!("No provider can serve model {}", model)),
+    }
+}
+
+/// Connect to the target, picking the endpoint when it's balanced
+async fn connect_target(
+    state: &AppState,
+    alias: &str,
+    provider: &Model,
+    target: &Target,
+    request_body: &Value,
+    started: Instant,
+) -> Result<Connection, UpstreamError> {
+    let (url, in_flight) = match target {
+        Target::Balanced => {
+            let (url, in_flight) = state.balancer.pick(provider);
+            (url, Some(in_flight))
+        }
+        Target::Fixed(url) => (url.clone(), None),
+    };
+
+    let provider_name = provider.provider.to_string();
+    let result = connect_with_key(state, alias, provider, &url, request_body, started).await;
+    if let Err(e) = &result {
+        state
+            .metrics
+            .error(alias, &provider_name, e.kind(), e.status());
+    }
+    if in_flight.is_some() {
+        let failed = match &result {
+            Ok(_) => false,
+            Err(UpstreamError::Request(_) | UpstreamError::Timeout(..)) => true,
+            Err(UpstreamError::Status { status, .. }) => status.is_server_error(),
+        };
+        state.balancer.report(provider, &url, failed);
+    }
+
+    result.map(|(status, body)| Connection {
+        body,
+        status,
+        served_by: alias.to_string(),
+        request: request_body.clone(),
+        provider: provider_name,
+        pricing: provider.pricing.clone(),
+        _in_flight: in_flight,
+    })
+}
+
+/// Connect with the next key of the model and record how the key did
+async fn connect_with_key(
+    state: &AppState,
+    alias: &str,
+    provider: &Model,
+    url: &str,
+    request_body: &Value,
+    started: Instant,
+) -> Result<(Option<StatusCode>, UpstreamBody), UpstreamError> {
+    let api_key = state.keys.pick(alias, provider);
+    let result = connect(provider, url, request_body, &api_key, started).await;
+    if let Err(UpstreamError::Status {
+        status,
+        retry_after,
+        ..
+    }) = &result
+    {
+        state.keys.report(&api_key, status.as_u16(), *retry_after);
+    }
+    result
+}
+
+/// Send the request and wait for the first chunk of the response within the
+/// timeouts, `total_secs` counting from `started`
+async fn connect(
+    provider: &Model,
+    url: &str,
+    request_body: &Value,
+    api_key: &str,
+    started: Instant,
+) -> Result<(Option<StatusCode>, UpstreamBody), UpstreamError> {
+    let response = open(provider, url, request_body, api_key);
+    timeouts::first_chunk(&provider.timeouts, started, response).await
+}
+
+/// Send the request, or answer it without network for `replay` and `mock`.
+/// Returns the status of the response along with its body.
+async fn open(
+    provider: &Model,
+    url: &str,
+    request_body: &Value,
+    api_key: &str,
+) -> Result<(Option<StatusCode>, UpstreamBody), UpstreamError> {
+    match provider.provider {
+        ProviderName::Replay => return Ok((None, cassette::replay(provider, request_body)?)),
+        ProviderName::Mock => return Ok((None, mock::respond(provider, request_body).await?)),
+        _ => {}
+    }
 
+    let response = connect_http(provider, url, request_body, api_key).await;
+    let status = response.as_ref().ok().map(reqwest::Response::status);
+    let result = response.map(|response| -> UpstreamBody {
+        Box::pin(response.bytes_stream().map(|chunk| Ok(chunk?)))
+    });
+    let result = match &provider.cassettes {
+        Some(dir) => cassette::record(dir, provider, request_body, result),
+        None => result,
+    };
+    result.map(|body| (status, body))
+}
+
+async fn connect_http(
+    provider: &Model,
+    url: &str,
+    request_body: &Value,
+    api_key: &str,
+) -> Result<reqwest::Response, UpstreamError> {
+    let mut client = Client::builder();
+    let connect_timeout = provider.timeouts.connect_secs.map(Duration::from_secs);
+    if let Some(connect_timeout) = connect_timeout {
+        client = client.connect_timeout(connect_timeout);
+    }
     let response = client
-        .post(&provider.url)
+        .build()?
+        .post(url)
         .header("Content-Type", "application/json")
         .header("Authorization", format!("Bearer {}", api_key))
-        .json(&request_body)
+        .json(request_body)
         .send()
-        .await?;
+        .await
+        .map_err(|e| match connect_timeout {
+            Some(limit) if e.is_connect() && e.is_timeout() => {
+                UpstreamError::Timeout(Timeout::Connect, limit)
+            }
+            _ => UpstreamError::Request(e),
+        })?;
 
     let status = response.status();
     if !status.is_success() {
-        let error_message = response.text().await?;
-        return Err(anyhow::anyhow!("API请求失败: {}:{}", status, error_message));
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let message = response.text().await?;
+        return Err(UpstreamError::Status {
+            status,
+            message,
+            retry_after,
+        });
     }
 
+    Ok(response)
+}
+
+fn send(
+    ctx: &Dispatch<'_>,
+    connection: Connection,
+    mut log: RequestLog,
+    chat_type: ChatType,
+    on_done: Option<OnDone>,
+    cache_key: Option<String>,
+) -> impl Stream<Item = Result<String, anyhow::Error>> + Unpin + Send {
+    log.served(&connection.served_by, &connection.provider);
+    // 将模型名称中的 "-" 替换为 ":"
+    let model = names::ollama_name(&ctx.model);
+    let state = ctx.state.clone();
+    let client = ctx.client.clone();
+    let costs_file = ctx.config.accounting.costs_file.clone();
+    let cache_config = ctx.config.cache.clone();
+    let transcript = ctx
+        .records_transcript()
+        .then(|| (ctx.config.transcripts.clone(), ctx.request.clone()));
+
     let done_flag = Arc::new(AtomicBool::new(false));
     let done_flag_clone = done_flag.clone();
     let model_clone = model.clone();
-    let chat_type_clone = chat_type.clone();
+    let chat_type_clone = chat_type;
 
     let stream = try_stream! {
+        let mut on_done = on_done;
+        let mut cache_key = cache_key;
+        let mut response_text = String::new();
+        // contents of the chunks, kept for the cache
+        let mut chunks = Vec::new();
+        let mut usage = None;
         let mut buf = BytesMut::new();
-        let mut stream_bytes = response.bytes_stream();
+        let Connection { body, status, served_by, request, provider, pricing, _in_flight } = connection;
+        let _streaming = state.metrics.stream(&served_by, &provider);
+        let mut first_token = None;
+        let mut stream_bytes = body;
 
         while let Some(result) = stream_bytes.next().await {
-            let bytes = result?;
+            let bytes = match result {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    let timeout = e
+                        .downcast_ref::<UpstreamError>()
+                        .filter(|e| e.is_timeout())
+                        .map(UpstreamError::kind);
+                    if let Some(kind) = timeout {
+                        // the status is sent already, end the stream with an error like Ollama does
+                        state.metrics.error(&served_by, &provider, kind, None);
+                        log.failed(&e);
+                        yield format!("{}\n", json!({ "error": e.to_string() }));
+                        break;
+                    }
+
+                    state.metrics.error(&served_by, &provider, "stream", None);
+                    log.failed(&anyhow::anyhow!("stream interrupted: {}", e));
+                    Err(e)?
+                }
+            };
             buf.extend_from_slice(&bytes);
 
             while let Some(position) = buf.windows(2).position(|window| window == b"\n\n") {
                 let line_bytes = buf.split_to(position + 2);
                 let line = String::from_utf8_lossy(&line_bytes).trim().to_string();
                 if !line.is_empty() {
-                    if let Some(content) = process_line(&line, &model_clone, &chat_type_clone, &done_flag_clone) {
+                    let streamed = response_text.len();
+                    if let Some(content) = process_line(&line, &model_clone, &chat_type_clone, &done_flag_clone, &mut response_text, &mut usage) {
+                        if cache_key.is_some() {
+                            chunks.push(response_text[streamed..].to_string());
+                        }
+                        if first_token.is_none() {
+                            let elapsed = log.started.elapsed();
+                            first_token = Some(elapsed);
+                            state.metrics.first_token(&served_by, &provider, elapsed);
+                        }
                         // trim \n\n from the start or end of the content and add \n\n to the end of the content
                         let mut content_with_newline = content.clone();
                         content_with_newline = content_with_newline.trim_start_matches("\n\n").to_string();
-                        content_with_newline.push_str("\n");
+                        content_with_newline.push('\n');
                         yield content_with_newline;
                     }
                 }
             }
 
             if done_flag_clone.load(Ordering::SeqCst) {
-                // contruct a chat message
-                // this is zed.dev format, not in ollama format
-                let message = json!({
-                    "role": "assistant",
-                    "content": "",
-                    "images": null
-                });
-
-                let done = json!({
-                    "model": model_clone,
-                    "created_at": chrono::Utc::now().to_rfc3339(),
-                    "response": "",
-                    "message": message,
-                    "done": true,
-                    "context": [1, 2, 3],
-                    "total_duration": 122112,
-                    "load_duration": 123112,
-                    "prompt_eval_count": 26,
-                    "prompt_eval_duration": 130079000,
-                    "eval_count": 259,
-                    "eval_duration": 2433122
-                });
+                let context = match on_done.take() {
+                    Some(on_done) => on_done(&response_text),
+                    None => json!([1, 2, 3]),
+                };
+
+                log.completed(status, &served_by, &provider, first_token, usage.as_ref());
+                if let Some((config, incoming)) = &transcript {
+                    let entry = json!({
+                        "timestamp": Utc::now().to_rfc3339(),
+                        "request_id": log.request_id,
+                        "client": log.client,
+                        "model": log.model,
+                        "served_by": served_by,
+                        "provider": provider,
+                        "request": incoming,
+                        "upstream_request": request,
+                        "response": response_text,
+                        "usage": usage,
+                        "latency_ms": log.started.elapsed().as_millis() as u64,
+                        "ttft_ms": first_token.map(|ttft| ttft.as_millis() as u64),
+                    });
+                    state.transcripts.record(config, &entry);
+                }
+                if let Some(usage) = &usage {
+                    record_usage(&state, &costs_file, client.as_ref(), &served_by, &provider, pricing.as_ref(), usage);
+                }
+                if let Some(key) = cache_key.take() {
+                    let entry = CachedResponse {
+                        key,
+                        served_by: served_by.clone(),
+                        chunks: std::mem::take(&mut chunks),
+                        usage,
+                    };
+                    state.cache.insert(&cache_config, entry);
+                }
+
+                let done = done_chunk(&model_clone, context, usage.as_ref());
                 // trim \n\n from the start or end of the content and add \n\n to the end of the content
                 let mut done_with_newline = done.to_string();
                 done_with_newline = done_with_newline.trim_start_matches("\n\n").to_string();
-                done_with_newline.push_str("\n");
+                done_with_newline.push('\n');
                 yield done_with_newline;
                 break;
             }
         }
+        if !done_flag_clone.load(Ordering::SeqCst) && !log.logged.load(Ordering::SeqCst) {
+            state.metrics.error(&served_by, &provider, "stream", None);
+            log.failed(&anyhow::anyhow!("stream ended before [DONE]"));
+        }
+    };
+
+    Box::pin(stream)
+}
+
+/// Send the input to an OpenAI compatible `/embeddings` endpoint,
+/// the returned data is ordered as the input
+pub async fn embed(
+    ctx: Dispatch<'_>,
+    provider: &Model,
+    input: Vec<String>,
+) -> Result<EmbeddingResponse, anyhow::Error> {
+    let mut request_body = json!({
+        "model": provider.model_name,
+        "input": input,
+    });
+    if let Some(dimensions) = provider.dimensions {
+        request_body["dimensions"] = json!(dimensions);
+    }
+
+    let mut log = RequestLog::new(&ctx, None);
+    let connection = connect_target(
+        &ctx.state,
+        &ctx.model,
+        provider,
+        &Target::Balanced,
+        &request_body,
+        ctx.started,
+    )
+    .await
+    .map_err(anyhow::Error::from)
+    .inspect_err(|e| log.failed(e))?;
+    log.served(&connection.served_by, &connection.provider);
+
+    let mut embeddings = read_embeddings(connection.body)
+        .await
+        .inspect_err(|e| log.failed(e))?;
+    embeddings.data.sort_by_key(|data| data.index);
+    let usage = Usage {
+        prompt_tokens: embeddings.usage.prompt_tokens,
+        completion_tokens: 0,
+        total_tokens: embeddings.usage.total_tokens,
     };
+    log.completed(
+        connection.status,
+        &connection.served_by,
+        &connection.provider,
+        None,
+        Some(&usage),
+    );
+    record_usage(
+        &ctx.state,
+        &ctx.config.accounting.costs_file,
+        ctx.client.as_ref(),
+        &connection.served_by,
+        &connection.provider,
+        connection.pricing.as_ref(),
+        &usage,
+    );
+    Ok(embeddings)
+}
+
+async fn read_embeddings(mut chunks: UpstreamBody) -> Result<EmbeddingResponse> {
+    let mut body = Vec::new();
+    while let Some(chunk) = chunks.next().await {
+        body.extend_from_slice(&chunk?);
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// The config and the alias of the requested model, which must be the model
+/// served by this proxy
+pub fn served_model(state: &AppState, requested: &str) -> Result<(Config, String)> {
+    let config = Config::from_file(&state.config_path).context("Failed to load config")?;
+    let model = config
+        .resolve(requested) // deepseek:chat -> deepseek-chat
+        .with_context(|| format!("Model {} not found", requested))?
+        .to_string();
+
+    // check model name if match in app state
+    if model != state.model_name {
+        return Err(anyhow::anyhow!(
+            "Model name not match in app state:{} != {}",
+            model,
+            state.model_name
+        ));
+    }
+    Ok((config, model))
+}
+
+/// Log a request that failed before it was dispatched, e.g. for an unknown model
+pub fn log_rejected(client: Option<&AuthClient>, model: &str, error: &anyhow::Error) {
+    logging::rejected(
+        client.map_or(ANONYMOUS, |client| client.name.as_str()),
+        Some(model),
+        error::status_of(error),
+        &error.to_string(),
+    );
+}
+
+/// Fields of the record logged once per request at `REQUEST_TARGET`.
+/// `status` is the status of the upstream response, if there was one.
+/// Dropped before it is completed or failed, the client went away
+/// and the request is logged and counted as cancelled. Each of these ends
+/// counts the request and its duration in the metrics, once.
+struct RequestLog {
+    state: Arc<AppState>,
+    request_id: String,
+    client: String,
+    /// Alias of the requested model
+    model: String,
+    /// Alias and provider of the model serving the request,
+    /// the requested model until an upstream accepts it
+    served_by: String,
+    provider: String,
+    /// Last message of the request, unless contents are redacted
+    prompt: Option<String>,
+    started: Instant,
+    logged: AtomicBool,
+}
+
+impl RequestLog {
+    fn new(ctx: &Dispatch<'_>, prompt: Option<String>) -> Self {
+        RequestLog {
+            state: ctx.state.clone(),
+            request_id: ctx.request_id.clone(),
+            client: ctx
+                .client
+                .as_ref()
+                .map_or(ANONYMOUS.to_string(), |client| client.name.clone()),
+            model: ctx.model.clone(),
+            served_by: ctx.model.clone(),
+            provider: ctx
+                .config
+                .get_model(&ctx.model)
+                .map(|model| model.provider.to_string())
+                .unwrap_or_default(),
+            prompt,
+            started: ctx.started,
+            logged: AtomicBool::new(false),
+        }
+    }
+
+    fn served(&mut self, served_by: &str, provider: &str) {
+        self.served_by = served_by.to_string();
+        self.provider = provider.to_string();
+    }
+
+    /// Count the request and its duration, labelled by the model that served it
+    fn measure(&self, served_by: &str, provider: &str) {
+        let metrics = &self.state.metrics;
+        metrics.request(served_by, provider);
+        metrics.duration(served_by, provider, self.started.elapsed());
+    }
+
+    fn completed(
+        &self,
+        status: Option<StatusCode>,
+        served_by: &str,
+        provider: &str,
+        first_token: Option<Duration>,
+        usage: Option<&Usage>,
+    ) {
+        self.logged.store(true, Ordering::SeqCst);
+        self.measure(served_by, provider);
+        info!(
+            target: REQUEST_TARGET,
+            request_id = %self.request_id,
+            client = %self.client,
+            model = %self.model,
+            served_by,
+            provider,
+            status = status.map(|status| status.as_u16()),
+            latency_ms = self.started.elapsed().as_millis() as u64,
+            ttft_ms = first_token.map(|ttft| ttft.as_millis() as u64),
+            prompt_tokens = usage.map(|usage| usage.prompt_tokens),
+            completion_tokens = usage.map(|usage| usage.completion_tokens),
+            prompt = self.prompt.as_deref(),
+            "request completed"
+        );
+    }
+
+    fn failed(&self, error: &anyhow::Error) {
+        self.logged.store(true, Ordering::SeqCst);
+        self.measure(&self.served_by, &self.provider);
+        let status = error
+            .downcast_ref::<UpstreamError>()
+            .and_then(UpstreamError::status);
+        warn!(
+            target: REQUEST_TARGET,
+            request_id = %self.request_id,
+            client = %self.client,
+            model = %self.model,
+            served_by = %self.served_by,
+            provider = %self.provider,
+            status,
+            latency_ms = self.started.elapsed().as_millis() as u64,
+            prompt = self.prompt.as_deref(),
+            error = %error,
+            "request failed"
+        );
+    }
+}
+
+impl Drop for RequestLog {
+    fn drop(&mut self) {
+        if self.logged.load(Ordering::SeqCst) {
+            return;
+        }
+        self.state.metrics.cancelled(&self.model);
+        self.measure(&self.served_by, &self.provider);
+        info!(
+            target: REQUEST_TARGET,
+            request_id = %self.request_id,
+            client = %self.client,
+            model = %self.model,
+            served_by = %self.served_by,
+            provider = %self.provider,
+            // nginx's "client closed request"
+            status = 499u16,
+            latency_ms = self.started.elapsed().as_millis() as u64,
+            prompt = self.prompt.as_deref(),
+            "request cancelled"
+        );
+    }
+}
 
-    Ok(Box::pin(stream))
+/// Count the tokens of a request against the client and add its cost to the ledger
+fn record_usage(
+    state: &AppState,
+    costs_file: &str,
+    client: Option<&AuthClient>,
+    served_by: &str,
+    provider: &str,
+    pricing: Option<&Pricing>,
+    usage: &Usage,
+) {
+    state.metrics.tokens(
+        served_by,
+        provider,
+        usage.prompt_tokens,
+        usage.completion_tokens,
+    );
+    if let Some(client) = client {
+        state.limits.record(&client.name, usage.total_tokens);
+    }
+    if let Some(pricing) = pricing {
+        let client = client.map_or(ANONYMOUS, |client| client.name.as_str());
+        state
+            .costs
+            .record(costs_file, client, served_by, pricing, usage);
+    }
+}
+
+/// A streamed chunk of the response in the Ollama format
+fn chunk(model: &str, chat_type: &ChatType, content: &str) -> Value {
+    let mut json_content = json!({
+        "model": model,
+        "created_at": Utc::now().to_rfc3339(),
+        "done": false
+    });
+
+    if *chat_type == ChatType::Chat {
+        json_content["message"] = json!({
+            "role": "assistant",
+            "content": content,
+            "images": null
+        });
+    } else {
+        json_content["response"] = json!(content);
+    }
+    json_content
+}
+
+/// The last chunk of the response, with the token counts of the upstream if known
+fn done_chunk(model: &str, context: Value, usage: Option<&Usage>) -> Value {
+    // contruct a chat message
+    // this is zed.dev format, not in ollama format
+    let message = json!({
+        "role": "assistant",
+        "content": "",
+        "images": null
+    });
+
+    let mut done = json!({
+        "model": model,
+        "created_at": chrono::Utc::now().to_rfc3339(),
+        "response": "",
+        "message": message,
+        "done": true,
+        "context": context,
+        "total_duration": 122112,
+        "load_duration": 123112,
+        "prompt_eval_count": 26,
+        "prompt_eval_duration": 130079000,
+        "eval_count": 259,
+        "eval_duration": 2433122
+    });
+    if let Some(usage) = usage {
+        done["prompt_eval_count"] = json!(usage.prompt_tokens);
+        done["eval_count"] = json!(usage.completion_tokens);
+    }
+    done
 }
 
 fn process_line(
@@ -140,6 +923,8 @@ fn process_line(
     model: &str,
     chat_type: &ChatType,
     done_flag: &Arc<AtomicBool>,
+    response_text: &mut String,
+    usage: &mut Option<Usage>,
 ) -> Option<String> {
     if line.trim() == "data: [DONE]" {
         done_flag.store(true, Ordering::SeqCst);
@@ -148,34 +933,27 @@ fn process_line(
         let json_str = line.trim_start_matches("data: ").trim();
         match serde_json::from_str::<Value>(json_str) {
             Ok(json) => {
-                let content = json["choices"][0]["delta"]["content"]
+                // sent with the last content or in a chunk of its own
+                if let Ok(chunk_usage) = serde_json::from_value::<Usage>(json["usage"].clone()) {
+                    *usage = Some(chunk_usage);
+                }
+
+                // chat completions stream `delta.content`, text completions stream `text`
+                let choice = &json["choices"][0];
+                let content = choice["delta"]["content"]
                     .as_str()
+                    .or_else(|| choice["text"].as_str())
                     .unwrap_or("")
                     .to_string();
                 if !content.is_empty() {
-                    let mut json_content = json!({
-                        "model": model,
-                        "created_at": Utc::now().to_rfc3339(),
-                        "done": false
-                    });
-
-                    if *chat_type == ChatType::Chat {
-                        json_content["message"] = json!({
-                            "role": "assistant",
-                            "content": content,
-                            "images": null
-                        });
-                    } else {
-                        json_content["response"] = json!(content);
-                    }
-
-                    Some(json_content.to_string())
+                    response_text.push_str(&content);
+                    Some(chunk(model, chat_type, &content).to_string())
                 } else {
                     None
                 }
             }
             Err(e) => {
-                eprintln!("JSON解析错误: {}", e);
+                warn!("JSON解析错误: {}", e);
                 None
             }
         }
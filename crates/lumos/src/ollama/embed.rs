@@ -0,0 +1,122 @@
+/// Generate embeddings from an embedding model declared in the config file.
+/// https://github.com/ollama/ollama/blob/main/docs/api.md#generate-embeddings
+use anyhow::{Context, Result};
+use axum::extract::{Extension, Json, State};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::auth::Client;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::ollama::dispatch::log_rejected;
+use crate::ollama::{embed, Dispatch};
+use crate::structs::app::AppState;
+use crate::structs::ollama::{
+    EmbedInput, EmbedRequest, EmbedResponse, EmbeddingsRequest, EmbeddingsResponse,
+};
+use crate::structs::openai::{EmbeddingRequest, EmbeddingResponse};
+
+type HandlerResult<T> = Result<Json<T>, ApiError>;
+
+/// `POST /api/embed`
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    client: Option<Extension<Client>>,
+    Json(request): Json<EmbedRequest>,
+) -> HandlerResult<EmbedResponse> {
+    embed_batch(state, client.map(|Extension(client)| client), request)
+        .await
+        .map(Json)
+        .map_err(ApiError::from)
+}
+
+/// `POST /api/embeddings`, superseded by `/api/embed`
+pub async fn legacy_handler(
+    State(state): State<Arc<AppState>>,
+    client: Option<Extension<Client>>,
+    Json(request): Json<EmbeddingsRequest>,
+) -> HandlerResult<EmbeddingsResponse> {
+    let request = EmbedRequest {
+        model: request.model,
+        input: EmbedInput::Single(request.prompt),
+        truncate: None,
+        options: request.options,
+        keep_alive: request.keep_alive,
+    };
+
+    let client = client.map(|Extension(client)| client);
+    let mut response = embed_batch(state, client, request)
+        .await
+        .map_err(ApiError::from)?;
+    Ok(Json(EmbeddingsResponse {
+        embedding: response.embeddings.pop().unwrap_or_default(),
+    }))
+}
+
+/// `POST /v1/embeddings`, OpenAI compatible
+pub async fn openai_handler(
+    State(state): State<Arc<AppState>>,
+    client: Option<Extension<Client>>,
+    Json(request): Json<EmbeddingRequest>,
+) -> HandlerResult<EmbeddingResponse> {
+    openai_embed(state, client.map(|Extension(client)| client), request)
+        .await
+        .map(Json)
+        .map_err(ApiError::from)
+}
+
+async fn embed_batch(
+    state: Arc<AppState>,
+    client: Option<Client>,
+    req: EmbedRequest,
+) -> Result<EmbedResponse> {
+    let start = Instant::now();
+    let config = Config::from_file(&state.config_path).context("Failed to load config")?;
+    let model = config
+        .resolve(&req.model)
+        .with_context(|| format!("Model {} not found", req.model))
+        .inspect_err(|e| log_rejected(client.as_ref(), &req.model, e))?
+        .to_string();
+    let provider = config.get_model(&model).context("Provider not found")?;
+
+    let ctx = Dispatch::new(state.clone(), &config, model, client);
+    let response = embed(ctx, provider, req.input.into_vec()).await?;
+
+    Ok(EmbedResponse {
+        model: req.model,
+        embeddings: response.data.into_iter().map(|d| d.embedding).collect(),
+        total_duration: start.elapsed().as_nanos() as u64,
+        load_duration: 0,
+        prompt_eval_count: response.usage.prompt_tokens,
+    })
+}
+
+async fn openai_embed(
+    state: Arc<AppState>,
+    client: Option<Client>,
+    req: EmbeddingRequest,
+) -> Result<EmbeddingResponse> {
+    let config = Config::from_file(&state.config_path).context("Failed to load config")?;
+    let model = config
+        .resolve(&req.model)
+        .with_context(|| format!("Model {} not found", req.model))
+        .inspect_err(|e| log_rejected(client.as_ref(), &req.model, e))?
+        .to_string();
+    let mut provider = config
+        .get_model(&model)
+        .context("Provider not found")?
+        .clone();
+    if req.dimensions.is_some() {
+        provider.dimensions = req.dimensions;
+    }
+
+    let ctx = Dispatch::new(state.clone(), &config, model, client);
+    let mut response = embed(ctx, &provider, req.input.into_vec()).await?;
+    response.object = "list".to_string();
+    response.model = req.model;
+    for data in response.data.iter_mut() {
+        data.object = "embedding".to_string();
+    }
+
+    Ok(response)
+}
@@ -1,52 +1,203 @@
 use anyhow::{Context, Result};
 use axum::{
-    extract::{Json, State},
-    response::IntoResponse,
+    extract::{Extension, Json, State},
+    response::{IntoResponse, Response},
 };
+use serde_json::json;
 use std::sync::Arc;
 
-use crate::config::Config;
-use crate::ollama::dispatch;
+use crate::auth::Client;
+use crate::error::{ApiError, BadRequest};
+use crate::ollama::dispatch::{log_rejected, served_model};
+use crate::ollama::{complete, dispatch, render_template, Completion, Dispatch, OnDone};
 use crate::structs::app::AppState;
-use crate::structs::ollama::ChatType;
+use crate::structs::config::Model;
 use crate::structs::ollama::GenerateRequest;
 use crate::structs::ollama::Message;
+use crate::structs::ollama::{ChatOptions, ChatType};
 
 pub async fn handler(
     State(state): State<Arc<AppState>>,
+    client: Option<Extension<Client>>,
     Json(request): Json<GenerateRequest>,
-) -> Result<impl IntoResponse, (axum::http::StatusCode, String)> {
-    generate(State(state), Json(request))
-        .await
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+) -> Result<impl IntoResponse, ApiError> {
+    generate(
+        State(state),
+        client.map(|Extension(client)| client),
+        Json(request),
+    )
+    .await
+    .map_err(ApiError::from)
 }
 
 async fn generate(
     State(state): State<Arc<AppState>>,
+    client: Option<Client>,
     Json(req): Json<GenerateRequest>,
-) -> Result<impl IntoResponse, anyhow::Error> {
-    let model = &req.model.replacen(":", "-", 1); // deepseek:chat -> deepseek-chat
+) -> Result<Response, anyhow::Error> {
+    let (config, model) = served_model(&state, &req.model)
+        .inspect_err(|e| log_rejected(client.as_ref(), &req.model, e))?;
+    let provider = config.get_model(&model).context("Provider not found")?;
+    let options = match &req.options {
+        Some(options) => ChatOptions::from_map(options)
+            .context("Invalid options")
+            .inspect_err(|e| log_rejected(client.as_ref(), &model, e))?,
+        None => ChatOptions::default(),
+    };
+    let ctx = Dispatch::new(state.clone(), &config, model, client)
+        .with_request(&req)
+        .with_options(options);
 
-    // check model name if match in app state
-    if model != &state.model_name {
-        return Err(anyhow::anyhow!(
-            "Model name not match in app state:{} != {}",
-            model,
-            state.model_name
-        ));
+    // `context` only applies to the chat turns below: a raw, templated or
+    // fill-in-the-middle prompt is complete and its answer isn't remembered
+    let prompt = req.prompt.unwrap_or_default();
+    if let Some(suffix) = req.suffix.filter(|suffix| !suffix.is_empty()) {
+        return fill_in_middle(ctx, prompt, suffix).await;
     }
 
-    let config_path = &state.config_path;
+    // the prompt is already formatted by the client, send it untouched
+    if req.raw {
+        completion_endpoint(&ctx, provider, "raw")?;
+        return complete_prompt(ctx, prompt).await;
+    }
 
-    let config = Config::from_file(config_path).context("Failed to load config")?;
-    let provider = config.get_model(model).context("Provider not found")?;
+    // the rendered template is a complete prompt, the chat endpoint would
+    // wrap it in the template of the model again
+    if let Some(template) = req.template.filter(|template| !template.is_empty()) {
+        completion_endpoint(&ctx, provider, "template")?;
+        let prompt = render_template(&template, req.system.as_deref(), &prompt)
+            .inspect_err(|e| log_rejected(ctx.client.as_ref(), &ctx.model, e))?;
+        return complete_prompt(ctx, prompt).await;
+    }
 
-    let messages = vec![Message {
+    // turns of the previous requests, a new system replaces the old one
+    let mut messages = Vec::new();
+    let history = req
+        .context
+        .map(|handle| state.contexts.get(&handle))
+        .unwrap_or_default();
+    if let Some(system) = req.system.filter(|system| !system.is_empty()) {
+        messages.push(Message {
+            role: "system".to_string(),
+            content: system,
+            ..Default::default()
+        });
+        messages.extend(history.into_iter().filter(|msg| msg.role != "system"));
+    } else {
+        messages.extend(history);
+    }
+    messages.push(Message {
         role: "user".to_string(),
-        content: req.prompt.unwrap(),
+        content: prompt,
         ..Default::default()
-    }];
+    });
+
+    // Store the conversation with the response and return its handle as context
+    let mut turns = messages.clone();
+    let contexts = state.clone();
+    let on_done: OnDone = Box::new(move |response| {
+        turns.push(Message {
+            role: "assistant".to_string(),
+            content: response.to_string(),
+            ..Default::default()
+        });
+        json!(contexts.contexts.insert(turns))
+    });
 
     // Dispatch the request to the provider service and get the stream
-    dispatch(model, messages, provider, ChatType::Generate).await
+    Ok(dispatch(ctx, messages, ChatType::Generate, Some(on_done))
+        .await?
+        .into_response())
+}
+
+/// Refuse a `raw` or `template` prompt to a model without a completion
+/// endpoint, the chat endpoint would format it again
+fn completion_endpoint(ctx: &Dispatch<'_>, provider: &Model, field: &str) -> Result<()> {
+    if provider.completion_url.is_some() {
+        return Ok(());
+    }
+    let error = anyhow::Error::new(BadRequest(format!(
+        "Model {} needs a completion_url for {}",
+        ctx.model, field
+    )));
+    log_rejected(ctx.client.as_ref(), &ctx.model, &error);
+    Err(error)
+}
+
+/// Send the prompt untouched to the text completion endpoint
+async fn complete_prompt(ctx: Dispatch<'_>, prompt: String) -> Result<Response, anyhow::Error> {
+    let response = complete(ctx, |_| {
+        Some(Completion {
+            prompt: prompt.clone(),
+            suffix: None,
+            stop: Vec::new(),
+        })
+    })
+    .await?;
+
+    Ok(response.into_response())
+}
+
+/// Fill in the text between `prompt` and `suffix`, either with the native `suffix`
+/// parameter of the completion endpoint or with the FIM template of the model
+async fn fill_in_middle(
+    ctx: Dispatch<'_>,
+    prefix: String,
+    suffix: String,
+) -> Result<Response, anyhow::Error> {
+    let provider = ctx
+        .config
+        .get_model(&ctx.model)
+        .context("Provider not found")?;
+    if fim_completion(provider, &prefix, &suffix).is_none() {
+        let error = anyhow::anyhow!("Model {} does not support fill-in-the-middle", ctx.model);
+        log_rejected(ctx.client.as_ref(), &ctx.model, &error);
+        return Err(error);
+    }
+
+    let response = complete(ctx, |provider| fim_completion(provider, &prefix, &suffix)).await?;
+
+    Ok(response.into_response())
+}
+
+/// The completion of the text between `prefix` and `suffix`, `None` without a
+/// `completion_url`. A `fim_template` is set for the endpoints that have no
+/// native `suffix`, so it is used even if the endpoint has one.
+fn fim_completion(provider: &Model, prefix: &str, suffix: &str) -> Option<Completion> {
+    provider.completion_url.as_ref()?;
+    match &provider.fim_template {
+        Some(template) => Some(Completion {
+            prompt: fill(template, prefix, suffix),
+            suffix: None,
+            stop: provider.fim_stop.clone(),
+        }),
+        None => Some(Completion {
+            prompt: prefix.to_string(),
+            suffix: Some(suffix.to_string()),
+            stop: Vec::new(),
+        }),
+    }
+}
+
+/// `template` with `{prefix}` and `{suffix}` replaced in a single pass, so
+/// the placeholders are left as they are in the code being completed
+fn fill(template: &str, prefix: &str, suffix: &str) -> String {
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("{prefix}") {
+            filled.push_str(prefix);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{suffix}") {
+            filled.push_str(suffix);
+            rest = after;
+        } else {
+            filled.push('{');
+            rest = &rest[1..];
+        }
+    }
+    filled.push_str(rest);
+    filled
 }
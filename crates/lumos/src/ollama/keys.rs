@@ -0,0 +1,161 @@
+/// Rotation and health of upstream API keys.
+/// A key answered with 401/403 or 429 is benched for a while and picked again
+/// once the bench expires. State is kept per key, so aliases sharing a key
+/// share its health.
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::structs::config::{KeyRotation, Model};
+
+/// Bench of a rejected key, it's unlikely to recover on its own
+const UNAUTHORIZED_BENCH: Duration = Duration::from_secs(10 * 60);
+/// Bench of a rate limited key without `Retry-After`
+const RATE_LIMITED_BENCH: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct KeyState {
+    benched_until: Option<Instant>,
+    last_limited: Option<Instant>,
+    requests: u64,
+    failures: u64,
+}
+
+impl KeyState {
+    fn is_benched(&self, now: Instant) -> bool {
+        self.benched_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Key state shown by `GET /admin/keys`
+#[derive(Debug, Serialize)]
+pub struct KeyReport {
+    pub key: String,
+    pub state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub benched_for_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_limited_secs_ago: Option<u64>,
+    pub requests: u64,
+    pub failures: u64,
+}
+
+#[derive(Default)]
+pub struct KeyPool {
+    keys: Mutex<HashMap<String, KeyState>>,
+    cursors: Mutex<HashMap<String, usize>>,
+}
+
+impl KeyPool {
+    /// Pick the key for the next request to `alias`. If every key is benched
+    /// the one restored first is used rather than failing the request.
+    pub fn pick(&self, alias: &str, model: &Model) -> String {
+        let candidates = model.api_key.keys();
+        let mut keys = self.keys.lock().unwrap();
+        if candidates.len() <= 1 {
+            let key = candidates.first().cloned().unwrap_or_default();
+            keys.entry(key.clone()).or_default().requests += 1;
+            return key;
+        }
+
+        let now = Instant::now();
+        let mut cursors = self.cursors.lock().unwrap();
+        let cursor = cursors.entry(alias.to_string()).or_default();
+
+        // candidates in round-robin order starting at the cursor
+        let ordered = (0..candidates.len())
+            .map(|i| &candidates[(*cursor + i) % candidates.len()])
+            .collect::<Vec<_>>();
+        let state = |key: &String| keys.get(key);
+
+        let available = ordered
+            .iter()
+            .filter(|key| !state(key).is_some_and(|s| s.is_benched(now)));
+        let picked = match model.key_rotation {
+            KeyRotation::RoundRobin => available.copied().next(),
+            KeyRotation::LeastRecentlyLimited => available
+                .copied()
+                .min_by_key(|key| state(key).and_then(|s| s.last_limited)),
+        };
+        let picked = picked
+            .or_else(|| {
+                ordered
+                    .iter()
+                    .copied()
+                    .min_by_key(|key| state(key).and_then(|s| s.benched_until))
+            })
+            .cloned()
+            .unwrap_or_default();
+
+        let index = candidates
+            .iter()
+            .position(|key| *key == picked)
+            .unwrap_or(0);
+        *cursor = (index + 1) % candidates.len();
+        keys.entry(picked.clone()).or_default().requests += 1;
+
+        picked
+    }
+
+    /// Record the upstream status of a request made with `key`
+    pub fn report(&self, key: &str, status: u16, retry_after: Option<Duration>) {
+        let now = Instant::now();
+        let mut keys = self.keys.lock().unwrap();
+        let state = keys.entry(key.to_string()).or_default();
+
+        let bench = match status {
+            401 | 403 => UNAUTHORIZED_BENCH,
+            429 => {
+                state.last_limited = Some(now);
+                retry_after.unwrap_or(RATE_LIMITED_BENCH)
+            }
+            _ => return,
+        };
+        state.failures += 1;
+        state.benched_until = Some(now + bench);
+    }
+
+    /// State of the keys of a model, masked
+    pub fn report_model(&self, model: &Model) -> Vec<KeyReport> {
+        let now = Instant::now();
+        let keys = self.keys.lock().unwrap();
+
+        model
+            .api_key
+            .keys()
+            .iter()
+            .map(|key| {
+                let state = keys.get(key);
+                let benched_until = state
+                    .and_then(|s| s.benched_until)
+                    .filter(|until| *until > now);
+                KeyReport {
+                    key: mask(key),
+                    state: if benched_until.is_some() {
+                        "benched"
+                    } else {
+                        "active"
+                    },
+                    benched_for_secs: benched_until.map(|until| (until - now).as_secs()),
+                    last_limited_secs_ago: state
+                        .and_then(|s| s.last_limited)
+                        .map(|at| (now - at).as_secs()),
+                    requests: state.map_or(0, |s| s.requests),
+                    failures: state.map_or(0, |s| s.failures),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Keep only enough of a key to tell keys apart
+pub fn mask(key: &str) -> String {
+    let chars = key.chars().collect::<Vec<_>>();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    let head = chars[..3].iter().collect::<String>();
+    let tail = chars[chars.len() - 4..].iter().collect::<String>();
+    format!("{}...{}", head, tail)
+}
@@ -0,0 +1,102 @@
+/// The `mock` provider, answering with the OpenAI compatible stream scripted
+/// in the `mock` table of the model instead of calling an upstream.
+use bytes::Bytes;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::ollama::cassette::UpstreamBody;
+use crate::ollama::upstream::UpstreamError;
+use crate::structs::config::{MockMode, Model};
+
+const LOREM: &str = "lorem ipsum dolor sit amet consectetur adipiscing elit sed do eiusmod \
+    tempor incididunt ut labore et dolore magna aliqua";
+
+pub async fn respond(
+    provider: &Model,
+    request_body: &Value,
+) -> Result<UpstreamBody, UpstreamError> {
+    let script = provider.mock.clone();
+    tokio::time::sleep(Duration::from_millis(script.latency_ms)).await;
+
+    if let Some(status) = script.fail_status {
+        return Err(UpstreamError::Status {
+            status: StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            message: format!("mock failure {}", status),
+            retry_after: None,
+        });
+    }
+
+    let prompt = prompt(request_body);
+    let text = match script.mode {
+        MockMode::Echo => prompt.clone(),
+        MockMode::Fixed => script.text.clone(),
+        MockMode::Lorem => LOREM
+            .split_whitespace()
+            .cycle()
+            .take(script.tokens)
+            .collect::<Vec<_>>()
+            .join(" "),
+    };
+    let words = split_words(&text);
+    let usage = json!({
+        "prompt_tokens": split_words(&prompt).len(),
+        "completion_tokens": words.len(),
+        "total_tokens": split_words(&prompt).len() + words.len(),
+    });
+
+    // completions answer with `text`, chat completions with `delta.content`
+    let completion = request_body.get("prompt").is_some();
+    let model = provider.model_name.clone();
+    let pace =
+        (script.tokens_per_sec > 0.0).then(|| Duration::from_secs_f64(1.0 / script.tokens_per_sec));
+
+    let body = async_stream::try_stream! {
+        for (index, word) in words.iter().enumerate() {
+            if script.error_at_chunk == Some(index) {
+                Err(anyhow::anyhow!("mock error at chunk {}", index))?;
+            }
+            if let Some(pace) = pace {
+                tokio::time::sleep(pace).await;
+            }
+            let choice = if completion {
+                json!({ "index": 0, "text": word })
+            } else {
+                json!({ "index": 0, "delta": { "role": "assistant", "content": word } })
+            };
+            let chunk = json!({ "model": model, "choices": [choice] });
+            yield Bytes::from(format!("data: {}\n\n", chunk));
+        }
+        let last = json!({ "model": model, "choices": [], "usage": usage });
+        yield Bytes::from(format!("data: {}\n\ndata: [DONE]\n\n", last));
+    };
+    Ok(Box::pin(body))
+}
+
+/// The prompt, or the content of the last message
+fn prompt(request_body: &Value) -> String {
+    let text = match request_body.get("prompt") {
+        Some(prompt) => prompt.as_str(),
+        None => request_body["messages"]
+            .as_array()
+            .and_then(|messages| messages.last())
+            .and_then(|message| message["content"].as_str()),
+    };
+    text.unwrap_or_default().to_string()
+}
+
+/// Words with their leading whitespace, each one streamed as a chunk
+fn split_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for c in text.chars() {
+        if c.is_whitespace() && !word.trim().is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
@@ -1,11 +1,49 @@
+mod balancer;
+pub use balancer::{run_health_checks, Balancer};
+
+mod cache;
+pub use cache::{CachedResponse, ResponseCache};
+
+mod cassette;
+pub use cassette::path as cassette_path;
+
 mod chat;
 pub use chat::handler as chat_handler;
 
+mod context;
+pub use context::ContextStore;
+
 mod dispatch;
-pub use dispatch::dispatch;
+pub use dispatch::{complete, dispatch, embed, Completion, Dispatch, OnDone};
+
+mod embed;
+pub use embed::handler as embed_handler;
+pub use embed::legacy_handler as embeddings_handler;
+pub use embed::openai_handler as openai_embeddings_handler;
+
+mod keys;
+pub use keys::{mask as mask_key, KeyPool, KeyReport};
 
 mod generate;
 pub use generate::handler as generate_handler;
 
+mod mock;
+
+mod options;
+
+mod show;
+pub use show::handler as show_handler;
+
 mod tags;
 pub use tags::models;
+
+mod template;
+pub use template::render as render_template;
+
+mod truncate;
+pub use truncate::{estimate_tokens, fit as fit_context};
+
+mod timeouts;
+
+mod upstream;
+pub use upstream::{Timeout, UpstreamError};
@@ -0,0 +1,64 @@
+/// Sampling options and system prompt of the upstream requests. The options
+/// of the request take precedence over the `options` of the model serving it.
+use serde_json::{json, Value};
+
+use crate::structs::config::{Model, ModelOptions};
+use crate::structs::ollama::ChatOptions;
+
+/// Set the OpenAI sampling parameters of `request_body`
+pub fn apply(request_body: &mut Value, defaults: &ModelOptions, options: &ChatOptions) {
+    if let Some(temperature) = options.temperature.or(defaults.temperature) {
+        request_body["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = options.top_p.or(defaults.top_p) {
+        request_body["top_p"] = json!(top_p);
+    }
+    let max_tokens = match options.num_predict {
+        Some(num_predict) if num_predict > 0 => Some(num_predict as u64),
+        // no limit, whatever the model says
+        Some(_) => None,
+        None => defaults.max_tokens,
+    };
+    if let Some(max_tokens) = max_tokens {
+        request_body["max_tokens"] = json!(max_tokens);
+    }
+
+    let stop = options.stop.as_ref().unwrap_or(&defaults.stop);
+    if !stop.is_empty() {
+        // stop sequences the caller already set, e.g. for FIM, are kept
+        let mut all = request_body["stop"].as_array().cloned().unwrap_or_default();
+        for sequence in stop {
+            if !all.iter().any(|kept| kept == sequence) {
+                all.push(json!(sequence));
+            }
+        }
+        request_body["stop"] = json!(all);
+    }
+    if let Some(seed) = options.seed {
+        request_body["seed"] = json!(seed);
+    }
+}
+
+/// The messages with the `system_prompt` of the model merged in
+pub fn with_system_prompt(provider: &Model, messages: &[Value]) -> Vec<Value> {
+    let mut messages = messages.to_vec();
+    let Some(system_prompt) = provider
+        .system_prompt
+        .as_deref()
+        .filter(|prompt| !prompt.is_empty())
+    else {
+        return messages;
+    };
+
+    match messages.iter_mut().find(|msg| msg["role"] == "system") {
+        Some(system) => {
+            let content = match system["content"].as_str().unwrap_or_default() {
+                "" => system_prompt.to_string(),
+                content => format!("{}\n\n{}", system_prompt, content),
+            };
+            system["content"] = json!(content);
+        }
+        None => messages.insert(0, json!({ "role": "system", "content": system_prompt })),
+    }
+    messages
+}
@@ -0,0 +1,100 @@
+/// Show the information of a model, from its entry in the config file.
+/// https://github.com/ollama/ollama/blob/main/docs/api.md#show-model-information
+use axum::extract::{Json, State};
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::ollama::tags::details;
+use crate::structs::app::AppState;
+use crate::structs::config::Model;
+use crate::structs::ollama::ShowRequest;
+
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ShowRequest>,
+) -> Result<Json<Value>, ApiError> {
+    let config = Config::from_file(&state.config_path).map_err(|e| {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to load config: {}", e),
+        )
+    })?;
+    // only the served model is listed by `/api/tags`
+    let model = config
+        .resolve(&request.model)
+        .filter(|alias| *alias == state.model_name)
+        .and_then(|alias| config.get_model(alias))
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("model '{}' not found", request.model),
+            )
+        })?;
+
+    let parameters = parameters(model);
+    let mut modelfile = format!(
+        "# Modelfile generated by lumos\nFROM {}\n",
+        model.model_name
+    );
+    for parameter in parameters.lines() {
+        modelfile.push_str(&format!("PARAMETER {}\n", parameter));
+    }
+    if let Some(system) = &model.system_prompt {
+        modelfile.push_str(&format!("SYSTEM \"\"\"{}\"\"\"\n", system));
+    }
+
+    let mut response = json!({
+        "modelfile": modelfile,
+        "parameters": parameters,
+        "template": "{{ .Prompt }}",
+        "details": details(),
+        "model_info": {
+            "general.architecture": model.provider.to_string(),
+            "general.basename": model.model_name,
+        },
+        "capabilities": capabilities(model),
+        "modified_at": Utc::now().to_rfc3339(),
+    });
+    if let Some(system) = &model.system_prompt {
+        response["system"] = json!(system);
+    }
+    // read by clients like the editors to size their prompts
+    if let Some(length) = model.context_length {
+        response["model_info"][format!("{}.context_length", model.provider)] = json!(length);
+    }
+    Ok(Json(response))
+}
+
+/// The default options of the model, one `name value` per line
+fn parameters(model: &Model) -> String {
+    let options = &model.options;
+    let mut lines = Vec::new();
+    if let Some(temperature) = options.temperature {
+        lines.push(format!("temperature {}", temperature));
+    }
+    if let Some(top_p) = options.top_p {
+        lines.push(format!("top_p {}", top_p));
+    }
+    if let Some(max_tokens) = options.max_tokens {
+        lines.push(format!("num_predict {}", max_tokens));
+    }
+    for stop in &options.stop {
+        lines.push(format!("stop {:?}", stop));
+    }
+    lines.join("\n")
+}
+
+fn capabilities(model: &Model) -> Vec<&'static str> {
+    if model.dimensions.is_some() {
+        return vec!["embedding"];
+    }
+    let mut capabilities = vec!["completion"];
+    if model.fim_template.is_some() || model.completion_url.is_some() {
+        capabilities.push("insert");
+    }
+    capabilities
+}
@@ -0,0 +1,174 @@
+/// Render the prompt template of `/api/generate`.
+/// Only the subset of Go templates used by Ollama is supported:
+/// `{{ .System }}`, `{{ .Prompt }}`, `{{ .Response }}`, `{{ if }}`, `{{ else }}`,
+/// `{{ else if }}`, `{{ end }}`, comments and the `{{-` / `-}}` trim markers.
+/// https://github.com/ollama/ollama/blob/main/docs/template.md
+use anyhow::{anyhow, Result};
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    System,
+    Prompt,
+    Response,
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Field(Field),
+    If {
+        cond: Field,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Action(&'a str),
+}
+
+/// Render the template, the output stops at `{{ .Response }}` since the
+/// upstream generates the response from there
+pub fn render(template: &str, system: Option<&str>, prompt: &str) -> Result<String> {
+    let tokens = tokenize(template)?;
+    let mut tokens = tokens.into_iter();
+    let (nodes, terminator) = parse(&mut tokens)?;
+    if let Some(action) = terminator {
+        return Err(anyhow!("Unexpected {{{{ {} }}}} in template", action));
+    }
+
+    let mut output = String::new();
+    write(&nodes, system.unwrap_or(""), prompt, &mut output);
+    Ok(output)
+}
+
+fn tokenize(template: &str) -> Result<Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let mut text = &rest[..start];
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| anyhow!("Unclosed action in template"))?;
+
+        let mut action = &after[..end];
+        if let Some(trimmed) = action.strip_prefix('-') {
+            text = text.trim_end();
+            action = trimmed;
+        }
+        rest = &after[end + 2..];
+        if let Some(trimmed) = action.strip_suffix('-') {
+            rest = rest.trim_start();
+            action = trimmed;
+        }
+
+        tokens.push(Token::Text(text));
+        tokens.push(Token::Action(action.trim()));
+    }
+    tokens.push(Token::Text(rest));
+
+    Ok(tokens)
+}
+
+/// Parse nodes until an `else` or `end` action, which is returned as terminator
+fn parse<'a>(tokens: &mut impl Iterator<Item = Token<'a>>) -> Result<(Vec<Node>, Option<&'a str>)> {
+    let mut nodes = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        let action = match token {
+            Token::Text(text) => {
+                if !text.is_empty() {
+                    nodes.push(Node::Text(text.to_string()));
+                }
+                continue;
+            }
+            Token::Action(action) => action,
+        };
+
+        if action.starts_with("/*") && action.ends_with("*/") {
+            continue;
+        } else if action == "end" || action == "else" || action.starts_with("else if ") {
+            return Ok((nodes, Some(action)));
+        } else if let Some(cond) = action.strip_prefix("if ") {
+            nodes.push(parse_if(field(cond)?, tokens)?);
+        } else {
+            nodes.push(Node::Field(field(action)?));
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+fn parse_if<'a>(cond: Field, tokens: &mut impl Iterator<Item = Token<'a>>) -> Result<Node> {
+    let (then, terminator) = parse(tokens)?;
+    let otherwise = match terminator {
+        Some("end") => Vec::new(),
+        Some("else") => match parse(tokens)? {
+            (otherwise, Some("end")) => otherwise,
+            _ => return Err(anyhow!("Missing {{{{ end }}}} in template")),
+        },
+        // `else if` shares the `end` of the outer `if`
+        Some(action) if action.starts_with("else if ") => {
+            vec![parse_if(field(&action["else if ".len()..])?, tokens)?]
+        }
+        _ => return Err(anyhow!("Missing {{{{ end }}}} in template")),
+    };
+
+    Ok(Node::If {
+        cond,
+        then,
+        otherwise,
+    })
+}
+
+fn field(name: &str) -> Result<Field> {
+    match name.trim() {
+        ".System" => Ok(Field::System),
+        ".Prompt" => Ok(Field::Prompt),
+        ".Response" => Ok(Field::Response),
+        other => Err(anyhow!("Unsupported template action: {}", other)),
+    }
+}
+
+/// Returns false once `{{ .Response }}` is reached
+fn write(nodes: &[Node], system: &str, prompt: &str, output: &mut String) -> bool {
+    let value = |field: Field| match field {
+        Field::System => system,
+        Field::Prompt => prompt,
+        Field::Response => "",
+    };
+
+    for node in nodes {
+        let more = match node {
+            Node::Text(text) => {
+                output.push_str(text);
+                true
+            }
+            Node::Field(Field::Response) => false,
+            Node::Field(field) => {
+                output.push_str(value(*field));
+                true
+            }
+            Node::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let branch = if value(*cond).is_empty() {
+                    otherwise
+                } else {
+                    then
+                };
+                write(branch, system, prompt, output)
+            }
+        };
+        if !more {
+            return false;
+        }
+    }
+
+    true
+}
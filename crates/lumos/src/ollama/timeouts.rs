@@ -0,0 +1,89 @@
+/// The `timeouts` of a model applied to its upstream responses. Waiting for
+/// the first chunk is part of connecting, so running out of `first_byte_secs`
+/// or `total_secs` before anything streams falls back like a connect error.
+/// Once the response streams, `idle_secs` and `total_secs` end it with an error.
+use futures_util::stream::{self, StreamExt};
+use reqwest::StatusCode;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::ollama::cassette::UpstreamBody;
+use crate::ollama::upstream::{Timeout, UpstreamError};
+use crate::structs::config::Timeouts;
+
+/// Wait for the response and its first chunk, then enforce the limits on the rest
+pub async fn first_chunk(
+    timeouts: &Timeouts,
+    started: Instant,
+    response: impl Future<Output = Result<(Option<StatusCode>, UpstreamBody), UpstreamError>>,
+) -> Result<(Option<StatusCode>, UpstreamBody), UpstreamError> {
+    let first = async {
+        let (status, mut body) = response.await?;
+        let first = body.next().await;
+        Ok::<_, UpstreamError>((status, first, body))
+    };
+    let limit = earliest(
+        started,
+        [
+            (Timeout::FirstByte, timeouts.first_byte_secs),
+            (Timeout::Total, timeouts.total_secs),
+        ],
+    );
+    let (status, first, body) = match limit {
+        Some((timeout, limit, wait)) => tokio::time::timeout(wait, first)
+            .await
+            .map_err(|_| UpstreamError::Timeout(timeout, limit))??,
+        None => first.await?,
+    };
+
+    let body = Box::pin(stream::iter(first).chain(body));
+    Ok((status, streaming(timeouts, started, body)))
+}
+
+fn streaming(timeouts: &Timeouts, started: Instant, body: UpstreamBody) -> UpstreamBody {
+    if timeouts.idle_secs.is_none() && timeouts.total_secs.is_none() {
+        return body;
+    }
+    let timeouts = timeouts.clone();
+    Box::pin(async_stream::try_stream! {
+        let mut body = body;
+        loop {
+            let limit = earliest(
+                started,
+                [
+                    (Timeout::Idle, timeouts.idle_secs),
+                    (Timeout::Total, timeouts.total_secs),
+                ],
+            );
+            let next = match limit {
+                Some((timeout, limit, wait)) => tokio::time::timeout(wait, body.next())
+                    .await
+                    .map_err(|_| UpstreamError::Timeout(timeout, limit))?,
+                None => body.next().await,
+            };
+            match next {
+                Some(chunk) => yield chunk?,
+                None => break,
+            }
+        }
+    })
+}
+
+/// The limit that runs out first with how long is left of it,
+/// idle and first byte limits start now, the total one at `started`
+fn earliest(
+    started: Instant,
+    limits: [(Timeout, Option<u64>); 2],
+) -> Option<(Timeout, Duration, Duration)> {
+    limits
+        .into_iter()
+        .filter_map(|(timeout, secs)| {
+            let limit = Duration::from_secs(secs?);
+            let wait = match timeout {
+                Timeout::Total => limit.saturating_sub(started.elapsed()),
+                _ => limit,
+            };
+            Some((timeout, limit, wait))
+        })
+        .min_by_key(|(_, _, wait)| *wait)
+}
@@ -0,0 +1,180 @@
+/// Truncation of the history to the context window of the model. System
+/// messages and the last message are always kept, the oldest of the other
+/// messages are dropped, or summarised, until the estimated tokens of the
+/// messages and of the response fit in the window.
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::structs::config::{Model, Tokenizer, Truncation};
+use crate::structs::ollama::ChatOptions;
+
+/// Tokens of the role and separators of a message
+const MESSAGE_OVERHEAD: u64 = 4;
+/// Characters of each turn quoted by a summary
+const EXCERPT_CHARS: usize = 80;
+
+impl Tokenizer {
+    /// Guess the family from the upstream model name
+    pub fn guess(model_name: &str) -> Tokenizer {
+        let name = model_name.to_lowercase();
+        if name.starts_with("glm") || name.starts_with("chatglm") || name.starts_with("codegeex") {
+            Tokenizer::Glm
+        } else if name.starts_with("deepseek") {
+            Tokenizer::DeepSeek
+        } else if name.starts_with("qwen") {
+            Tokenizer::Qwen
+        } else {
+            Tokenizer::Generic
+        }
+    }
+
+    /// Tokens per character of English and of Chinese text
+    fn rates(self) -> (f64, f64) {
+        match self {
+            Tokenizer::Glm => (0.25, 0.65),
+            Tokenizer::DeepSeek => (0.3, 0.6),
+            Tokenizer::Qwen => (0.25, 0.7),
+            Tokenizer::Generic => (0.3, 1.0),
+        }
+    }
+}
+
+/// Estimated tokens of a text, rounded up
+pub fn estimate_tokens(tokenizer: Tokenizer, text: &str) -> u64 {
+    let (ascii_rate, wide_rate) = tokenizer.rates();
+    let ascii = text.chars().filter(char::is_ascii).count();
+    let wide = text.chars().count() - ascii;
+    (ascii as f64 * ascii_rate + wide as f64 * wide_rate).ceil() as u64
+}
+
+fn message_tokens(tokenizer: Tokenizer, message: &Value) -> u64 {
+    estimate_tokens(tokenizer, message["content"].as_str().unwrap_or_default()) + MESSAGE_OVERHEAD
+}
+
+/// The messages that fit in the context window of the model, the smallest
+/// of its `context_length` and of `num_ctx`, minus the tokens of the response
+pub fn fit(provider: &Model, options: &ChatOptions, messages: Vec<Value>) -> Vec<Value> {
+    let window = match (provider.context_length, options.num_ctx) {
+        (Some(length), Some(num_ctx)) => length.min(num_ctx),
+        (Some(length), None) => length,
+        (None, Some(num_ctx)) => num_ctx,
+        (None, None) => return messages,
+    };
+    let response = match options.num_predict {
+        Some(num_predict) if num_predict > 0 => num_predict as u64,
+        _ => provider.options.max_tokens.unwrap_or(window / 8),
+    };
+    let budget = window.saturating_sub(response);
+
+    let tokenizer = provider
+        .tokenizer
+        .unwrap_or_else(|| Tokenizer::guess(&provider.model_name));
+    let tokens = |messages: &[Value]| -> u64 {
+        messages
+            .iter()
+            .map(|message| message_tokens(tokenizer, message))
+            .sum()
+    };
+    if tokens(&messages) <= budget {
+        return messages;
+    }
+
+    // system messages and the last message stay, the others are candidates
+    let last = messages.len() - 1;
+    let (kept, mut turns): (Vec<_>, Vec<_>) = messages
+        .into_iter()
+        .enumerate()
+        .partition(|(index, message)| *index == last || message["role"] == "system");
+    let fixed = kept
+        .iter()
+        .map(|(_, message)| message_tokens(tokenizer, message))
+        .sum::<u64>();
+
+    let mut dropped = Vec::new();
+    let mut summary = None;
+    loop {
+        let used = fixed
+            + summary.as_ref().map_or(0, |s| message_tokens(tokenizer, s))
+            + turns
+                .iter()
+                .map(|(_, message)| message_tokens(tokenizer, message))
+                .sum::<u64>();
+        if used <= budget || turns.is_empty() {
+            if used > budget {
+                warn!(
+                    "Messages to {} don't fit in {} tokens even without history",
+                    provider.model_name, budget
+                );
+            }
+            break;
+        }
+
+        dropped.push(turns.remove(0).1);
+        // the history starts with a question, not with an answer or with
+        // the results of the tool calls of a dropped answer
+        while turns
+            .first()
+            .is_some_and(|(_, message)| message["role"] == "assistant" || message["role"] == "tool")
+        {
+            dropped.push(turns.remove(0).1);
+        }
+        if provider.truncation == Truncation::Summarize {
+            summary = summarize(&dropped, tokenizer, budget.saturating_sub(fixed) / 4);
+        }
+    }
+    info!(
+        "Dropped {} messages to {} to fit in {} tokens",
+        dropped.len(),
+        provider.model_name,
+        budget
+    );
+
+    let mut messages = kept.into_iter().chain(turns).collect::<Vec<_>>();
+    messages.sort_by_key(|(index, _)| *index);
+    let mut messages = messages
+        .into_iter()
+        .map(|(_, message)| message)
+        .collect::<Vec<_>>();
+    if let Some(summary) = summary {
+        let at = messages
+            .iter()
+            .take_while(|message| message["role"] == "system")
+            .count();
+        messages.insert(at, summary);
+    }
+    messages
+}
+
+/// A system message quoting the beginning of the most recent dropped
+/// turns that fit in `max_tokens`
+fn summarize(dropped: &[Value], tokenizer: Tokenizer, max_tokens: u64) -> Option<Value> {
+    let header = "Summary of the earlier conversation:";
+    let mut lines = Vec::new();
+    let mut used = estimate_tokens(tokenizer, header) + MESSAGE_OVERHEAD;
+    for message in dropped.iter().rev() {
+        let content = message["content"].as_str().unwrap_or_default();
+        let mut excerpt = content.chars().take(EXCERPT_CHARS).collect::<String>();
+        if excerpt.len() < content.len() {
+            excerpt.push('…');
+        }
+        let line = format!(
+            "- {}: {}",
+            message["role"].as_str().unwrap_or("user"),
+            excerpt
+        );
+        let line_tokens = estimate_tokens(tokenizer, &line) + 1;
+        if used + line_tokens > max_tokens {
+            break;
+        }
+        used += line_tokens;
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    lines.reverse();
+    Some(json!({
+        "role": "system",
+        "content": format!("{}\n{}", header, lines.join("\n")),
+    }))
+}
@@ -0,0 +1,161 @@
+use rand::Rng;
+use reqwest::StatusCode;
+use std::fmt;
+use std::time::Duration;
+
+use crate::structs::config::RetryPolicy;
+
+/// One of the `timeouts` of a model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timeout {
+    Connect,
+    FirstByte,
+    Idle,
+    Total,
+}
+
+/// Failure of an upstream request before its response streams,
+/// or for timeouts while it streams
+#[derive(Debug)]
+pub enum UpstreamError {
+    /// The request did not reach the upstream or got no response
+    Request(reqwest::Error),
+    /// The upstream took longer than the configured limit
+    Timeout(Timeout, Duration),
+    /// The upstream answered with a non-2xx status
+    Status {
+        status: StatusCode,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+}
+
+impl UpstreamError {
+    /// Whether another attempt or target may succeed:
+    /// connect errors, 429 and 5xx
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            UpstreamError::Request(e) => e.is_connect(),
+            UpstreamError::Timeout(..) => true,
+            UpstreamError::Status { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+        }
+    }
+
+    /// Short name of the failure for metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            UpstreamError::Request(e) if e.is_connect() => "connect",
+            UpstreamError::Request(e) if e.is_timeout() => "timeout",
+            UpstreamError::Request(_) => "request",
+            UpstreamError::Timeout(Timeout::Connect, _) => "connect_timeout",
+            UpstreamError::Timeout(Timeout::FirstByte, _) => "first_byte_timeout",
+            UpstreamError::Timeout(Timeout::Idle, _) => "idle_timeout",
+            UpstreamError::Timeout(Timeout::Total, _) => "total_timeout",
+            UpstreamError::Status { .. } => "status",
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, UpstreamError::Timeout(..))
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            UpstreamError::Request(e) => e.status().map(|status| status.as_u16()),
+            UpstreamError::Timeout(..) => None,
+            UpstreamError::Status { status, .. } => Some(status.as_u16()),
+        }
+    }
+
+    /// Delay before attempt `attempt + 1` of the same target, `None` if the
+    /// policy doesn't retry this error or the upstream asks to wait too long
+    pub fn retry_delay(&self, policy: &RetryPolicy, attempt: u32) -> Option<Duration> {
+        if attempt >= policy.max_attempts {
+            return None;
+        }
+
+        let retry_after = match self {
+            UpstreamError::Request(e) if policy.connect_errors && e.is_connect() => None,
+            UpstreamError::Timeout(..) if policy.timeouts => None,
+            UpstreamError::Status {
+                status,
+                retry_after,
+                ..
+            } if policy.statuses.contains(&status.as_u16()) => *retry_after,
+            _ => return None,
+        };
+
+        let max_delay = Duration::from_millis(policy.max_delay_ms);
+        let mut delay = Duration::from_millis(policy.base_delay_ms)
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(max_delay);
+        if policy.jitter {
+            delay = delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+        }
+
+        match retry_after {
+            Some(retry_after) if retry_after > max_delay => None,
+            Some(retry_after) => Some(delay.max(retry_after)),
+            None => Some(delay),
+        }
+    }
+}
+
+/// Parse a `Retry-After` header, either delay seconds or an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamError::Request(e) => write!(f, "API请求失败: {}", e),
+            UpstreamError::Timeout(timeout, after) => {
+                let secs = after.as_secs();
+                match timeout {
+                    Timeout::Connect => {
+                        write!(
+                            f,
+                            "API请求超时: not connected within {}s (connect_secs)",
+                            secs
+                        )
+                    }
+                    Timeout::FirstByte => {
+                        write!(
+                            f,
+                            "API请求超时: no response within {}s (first_byte_secs)",
+                            secs
+                        )
+                    }
+                    Timeout::Idle => {
+                        write!(f, "API请求超时: stream idle for {}s (idle_secs)", secs)
+                    }
+                    Timeout::Total => {
+                        write!(f, "API请求超时: not finished within {}s (total_secs)", secs)
+                    }
+                }
+            }
+            UpstreamError::Status {
+                status, message, ..
+            } => {
+                write!(f, "API请求失败: {}:{}", status, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UpstreamError {}
+
+impl From<reqwest::Error> for UpstreamError {
+    fn from(e: reqwest::Error) -> Self {
+        UpstreamError::Request(e)
+    }
+}
@@ -0,0 +1,82 @@
+/// JSON files of the state kept across restarts, the usage of the clients and
+/// the cost ledger. A file is replaced in one rename and written on a blocking
+/// thread, one write at a time, so the stream path never waits for the disk.
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// State saved to a file in the background
+pub trait Persisted: Send + 'static {
+    type Saved: Serialize;
+    /// What the file holds, for the logs
+    const NAME: &'static str;
+
+    fn writes(&mut self) -> &mut Writes;
+
+    /// The file and what to write to it, `None` without a file
+    fn snapshot(&self) -> Option<(String, Self::Saved)>;
+}
+
+/// Writes of the file of a state
+#[derive(Default)]
+pub struct Writes {
+    /// A write is under way
+    saving: bool,
+    /// The state changed since the write under way started
+    dirty: bool,
+}
+
+/// Read `path`, `None` if the file doesn't exist yet
+pub fn read<T: DeserializeOwned>(path: &str) -> Result<Option<T>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write then rename, a crash never leaves a truncated file
+pub fn write(path: &str, value: &impl Serialize) -> Result<()> {
+    let tmp = format!("{}.tmp", path);
+    std::fs::write(&tmp, serde_json::to_vec_pretty(value)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Save `state`, locked from `shared`, on a blocking thread. If a write is
+/// under way it writes the file again once it is done instead.
+pub fn save<S: Persisted>(state: &mut S, shared: &Arc<Mutex<S>>) {
+    let writes = state.writes();
+    if writes.saving {
+        writes.dirty = true;
+        return;
+    }
+    writes.saving = true;
+    let shared = shared.clone();
+    tokio::task::spawn_blocking(move || save_until_unchanged(&shared));
+}
+
+/// Write the state until it no longer changes, without holding the lock
+fn save_until_unchanged<S: Persisted>(shared: &Mutex<S>) {
+    loop {
+        let snapshot = {
+            let mut state = shared.lock().unwrap();
+            state.writes().dirty = false;
+            state.snapshot()
+        };
+        if let Some((path, saved)) = snapshot {
+            if let Err(e) = write(&path, &saved) {
+                warn!("Failed to save {}: {}", S::NAME, e);
+            }
+        }
+
+        let mut state = shared.lock().unwrap();
+        let writes = state.writes();
+        if !writes.dirty {
+            writes.saving = false;
+            return;
+        }
+    }
+}
@@ -1,5 +1,35 @@
-#[derive(Clone)]
+use crate::costs::Ledger;
+use crate::limits::Limits;
+use crate::metrics::Metrics;
+use crate::ollama::{Balancer, ContextStore, KeyPool, ResponseCache};
+use crate::transcripts::Transcripts;
+
 pub struct AppState {
     pub model_name: String,
     pub config_path: String,
+    pub contexts: ContextStore,
+    pub keys: KeyPool,
+    pub balancer: Balancer,
+    pub limits: Limits,
+    pub costs: Ledger,
+    pub metrics: Metrics,
+    pub transcripts: Transcripts,
+    pub cache: ResponseCache,
+}
+
+impl AppState {
+    pub fn new(model_name: String, config_path: String) -> Self {
+        AppState {
+            model_name,
+            config_path,
+            contexts: ContextStore::default(),
+            keys: KeyPool::default(),
+            balancer: Balancer::default(),
+            limits: Limits::default(),
+            costs: Ledger::default(),
+            metrics: Metrics::default(),
+            transcripts: Transcripts::default(),
+            cache: ResponseCache::default(),
+        }
+    }
 }
@@ -11,6 +11,12 @@ pub enum ProviderName {
     DeepSeek,
     #[serde(rename = "xinference")]
     Xinference,
+    /// Serves the responses recorded in `cassettes`, without network
+    #[serde(rename = "replay")]
+    Replay,
+    /// Serves the responses scripted in `mock`, without network
+    #[serde(rename = "mock")]
+    Mock,
 }
 
 impl fmt::Display for ProviderName {
@@ -19,10 +25,20 @@ impl fmt::Display for ProviderName {
             ProviderName::Zhipu => write!(f, "zhipu"),
             ProviderName::DeepSeek => write!(f, "deepseek"),
             ProviderName::Xinference => write!(f, "xinference"),
+            ProviderName::Replay => write!(f, "replay"),
+            ProviderName::Mock => write!(f, "mock"),
         }
     }
 }
 
+impl ProviderName {
+    /// Whether the provider reports the usage of a stream when asked with
+    /// `stream_options`, `mock` always does and `replay` serves what was recorded
+    pub fn streams_usage(&self) -> bool {
+        matches!(self, ProviderName::Zhipu | ProviderName::DeepSeek)
+    }
+}
+
 impl FromStr for ProviderName {
     type Err = anyhow::Error;
 
@@ -31,6 +47,8 @@ impl FromStr for ProviderName {
             "zhipu" => Ok(ProviderName::Zhipu),
             "deepseek" => Ok(ProviderName::DeepSeek),
             "xinference" => Ok(ProviderName::Xinference),
+            "replay" => Ok(ProviderName::Replay),
+            "mock" => Ok(ProviderName::Mock),
 
             _ => Err(anyhow::anyhow!("Invalid provider name: {}", s)),
         }
@@ -41,6 +59,448 @@ impl FromStr for ProviderName {
 pub struct Model {
     pub model_name: String,
     pub provider: ProviderName,
-    pub api_key: String,
+    pub api_key: ApiKey,
+    /// More names clients may use for this model, see `names`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// May be left out when `endpoints` is set
+    #[serde(default)]
+    pub url: String,
+    /// Output dimension of an embedding model, sent upstream as `dimensions`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<usize>,
+    /// Text completion endpoint, e.g. DeepSeek `/beta/completions`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_url: Option<String>,
+    /// Fill-in-the-middle prompt for models without a native `suffix` parameter,
+    /// `{prefix}` and `{suffix}` are replaced with the prompt and suffix.
+    /// Sent to `completion_url` instead of the `suffix`, when both are set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fim_template: Option<String>,
+    /// Stop sequences sent along with a `fim_template` prompt
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fim_stop: Vec<String>,
+    /// Aliases tried in order when this model fails before streaming
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<String>,
+    /// Retries of the same target before falling back, none if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    /// How the next key is picked when `api_key` is a list
+    #[serde(default)]
+    pub key_rotation: KeyRotation,
+    /// Upstream nodes serving the same model, used instead of `url`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<Endpoint>,
+    /// How requests are spread over `endpoints`
+    #[serde(default)]
+    pub balance: Balance,
+    /// Token prices, requests of models without one cost nothing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<Pricing>,
+    /// Record the exchanges with this model, see `[transcripts]`
+    #[serde(default)]
+    pub transcript: bool,
+    /// Directory the raw upstream responses are recorded to,
+    /// or replayed from with the `replay` provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cassettes: Option<String>,
+    /// Responses of the `mock` provider
+    #[serde(default)]
+    pub mock: MockScript,
+    /// Limits on the upstream requests
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    /// Sampling options used when the request doesn't set them
+    #[serde(default)]
+    pub options: ModelOptions,
+    /// Sent as the first system message, or before the system
+    /// message of the request if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    /// Context window in tokens, the oldest turns are truncated to fit
+    /// in it, or in the `num_ctx` of the request if smaller
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u64>,
+    /// How the oldest turns are removed
+    #[serde(default)]
+    pub truncation: Truncation,
+    /// Estimates the tokens of the messages, guessed from `model_name` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer: Option<Tokenizer>,
+}
+
+impl Model {
+    /// `endpoints`, or `url` as the only endpoint
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        if self.endpoints.is_empty() {
+            vec![Endpoint {
+                url: self.url.clone(),
+                weight: 1,
+            }]
+        } else {
+            self.endpoints.clone()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Endpoint {
     pub url: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Balance {
+    pub strategy: BalanceStrategy,
+    /// Consecutive failures that eject an endpoint
+    pub max_failures: u32,
+    /// How long an ejected endpoint is skipped
+    pub eject_secs: u64,
+    /// Path probed with `GET` on each endpoint, e.g. `/v1/models`,
+    /// no active health checks if not set
+    pub health_check_path: Option<String>,
+    pub health_check_secs: u64,
+}
+
+impl Default for Balance {
+    fn default() -> Self {
+        Balance {
+            strategy: BalanceStrategy::default(),
+            max_failures: 3,
+            eject_secs: 30,
+            health_check_path: None,
+            health_check_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceStrategy {
+    #[default]
+    WeightedRoundRobin,
+    LeastInFlight,
+}
+
+/// A single API key or a list of keys rotated between requests
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum ApiKey {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl ApiKey {
+    pub fn keys(&self) -> &[String] {
+        match self {
+            ApiKey::One(key) => std::slice::from_ref(key),
+            ApiKey::Many(keys) => keys,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyRotation {
+    #[default]
+    RoundRobin,
+    /// Prefer the key whose last 429 is the oldest
+    LeastRecentlyLimited,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts including the first one
+    pub max_attempts: u32,
+    /// Delay before the second attempt, doubled for each further attempt
+    pub base_delay_ms: u64,
+    /// Upper bound of a delay, a longer `Retry-After` gives up the target
+    pub max_delay_ms: u64,
+    /// Randomize each delay between half and the full value
+    pub jitter: bool,
+    /// Upstream statuses worth another attempt
+    pub statuses: Vec<u16>,
+    /// Retry when the upstream can't be connected
+    pub connect_errors: bool,
+    /// Retry when one of the `timeouts` is hit before the response streams
+    pub timeouts: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 10_000,
+            jitter: true,
+            statuses: vec![429, 500, 502, 503, 504],
+            connect_errors: true,
+            timeouts: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Truncation {
+    /// Drop the oldest turns
+    #[default]
+    Drop,
+    /// Replace the oldest turns with a system message quoting
+    /// the beginning of each of them
+    #[serde(alias = "summarise")]
+    Summarize,
+}
+
+/// Family of the tokenizer of a model, the tokens per character differ
+/// between English and Chinese text and from one family to the other
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Tokenizer {
+    Glm,
+    #[serde(rename = "deepseek")]
+    DeepSeek,
+    Qwen,
+    /// Overestimates Chinese text, for the other models
+    Generic,
+}
+
+/// Default sampling options of a model
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ModelOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+}
+
+/// Upstream request timeouts, no limit if not set
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Timeouts {
+    /// Establishing the connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_secs: Option<u64>,
+    /// From sending the request to the first chunk of the response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_byte_secs: Option<u64>,
+    /// Between two chunks of the response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_secs: Option<u64>,
+    /// The whole request, until the last byte of the response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_secs: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MockMode {
+    /// Repeat the last message or prompt
+    #[default]
+    Echo,
+    /// Answer with `text`
+    Fixed,
+    /// Answer with `tokens` words of lorem ipsum
+    Lorem,
+}
+
+/// Scripted responses of the `mock` provider
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct MockScript {
+    pub mode: MockMode,
+    pub text: String,
+    pub tokens: usize,
+    /// Pace of the streamed words, as fast as possible if 0
+    pub tokens_per_sec: f64,
+    /// Delay before the response starts
+    pub latency_ms: u64,
+    /// Answer with this status instead of streaming
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fail_status: Option<u16>,
+    /// Break the stream after this many chunks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_at_chunk: Option<usize>,
+}
+
+impl Default for MockScript {
+    fn default() -> Self {
+        MockScript {
+            mode: MockMode::Echo,
+            text: String::new(),
+            tokens: 50,
+            tokens_per_sec: 0.0,
+            latency_ms: 0,
+            fail_status: None,
+            error_at_chunk: None,
+        }
+    }
+}
+
+/// Prices per million tokens
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Pricing {
+    pub input: f64,
+    pub output: f64,
+    #[serde(default = "default_currency")]
+    pub currency: String,
+}
+
+fn default_currency() -> String {
+    "CNY".to_string()
+}
+
+impl Pricing {
+    pub fn cost(&self, input_tokens: u64, output_tokens: u64) -> f64 {
+        (self.input * input_tokens as f64 + self.output * output_tokens as f64) / 1_000_000.0
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AccountingConfig {
+    /// Where the cost of the requests is kept
+    #[serde(default = "default_costs_file")]
+    pub costs_file: String,
+}
+
+fn default_costs_file() -> String {
+    "costs.json".to_string()
+}
+
+impl Default for AccountingConfig {
+    fn default() -> Self {
+        AccountingConfig {
+            costs_file: default_costs_file(),
+        }
+    }
+}
+
+/// Where and how much of the transcripts is kept
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct TranscriptConfig {
+    pub dir: String,
+    /// Size after which the file is rotated
+    pub max_file_bytes: u64,
+    /// Rotated files kept besides the current one
+    pub max_files: usize,
+}
+
+impl Default for TranscriptConfig {
+    fn default() -> Self {
+        TranscriptConfig {
+            dir: "transcripts".to_string(),
+            max_file_bytes: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+/// The `[cache]` section, responses to requests at temperature 0 are
+/// kept and replayed to identical requests
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// Entries kept in memory, the least recently used is evicted first
+    pub max_entries: usize,
+    /// Keep the entries in this directory instead of in memory, unbounded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: false,
+            max_entries: 1000,
+            dir: None,
+        }
+    }
+}
+
+/// The `[server]` section, the command line options take precedence
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ServerConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    pub cors: CorsConfig,
+    /// Same as a top-level `[auth]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// Origins allowed to call the proxy from a browser, `"*"` for any,
+    /// CORS is disabled if empty
+    pub allow_origins: Vec<String>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allow_origins: vec!["*".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub clients: Vec<ClientKey>,
+    /// Where the token consumption of the clients is kept
+    #[serde(default = "default_usage_file")]
+    pub usage_file: String,
+}
+
+fn default_usage_file() -> String {
+    "usage.json".to_string()
+}
+
+/// A client of the proxy, sending `Authorization: Bearer <key>`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ClientKey {
+    pub name: String,
+    pub key: String,
+    /// Aliases the client may use, `"*"` for every model
+    pub models: Vec<String>,
+    /// May use the `/admin` routes
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_streams: Option<u32>,
+    /// Upstream tokens per UTC day
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_tokens: Option<u64>,
+    /// Upstream tokens per UTC month
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_tokens: Option<u64>,
+}
+
+impl ClientKey {
+    pub fn allows(&self, model: &str) -> bool {
+        self.models
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == model)
+    }
 }
@@ -23,7 +23,7 @@ pub struct GenerateRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub template: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub context: Option<Vec<u8>>,
+    pub context: Option<Vec<u32>>,
     #[serde(default)]
     pub stream: bool,
     #[serde(default, skip_serializing_if = "is_false")] // Treat missing as false
@@ -51,7 +51,7 @@ impl Default for GenerateRequest {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ChatRequest {
     pub model: String,
     pub messages: Vec<Message>,
@@ -67,25 +67,11 @@ pub struct ChatRequest {
     pub keep_alive: Option<Value>,
 }
 
-impl Default for ChatRequest {
-    fn default() -> Self {
-        ChatRequest {
-            model: String::new(),
-            messages: Vec::new(),
-            stream: false,
-            tools: None,
-            format: None,
-            options: None,
-            keep_alive: None,
-        }
-    }
-}
-
 fn default_stream() -> bool {
     true
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct Message {
     pub role: String,
     pub content: String,
@@ -95,17 +81,6 @@ pub struct Message {
     pub tool_calls: Option<Vec<ToolCall>>,
 }
 
-impl Default for Message {
-    fn default() -> Self {
-        Message {
-            role: String::new(),
-            content: String::new(),
-            images: None,
-            tool_calls: None,
-        }
-    }
-}
-
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Tool {
     pub type_: String,
@@ -133,11 +108,39 @@ pub struct FunctionCall {
     pub arguments: String,
 }
 
+/// `POST /api/show`, older clients send `name` instead of `model`
 #[derive(Debug, Deserialize, Serialize)]
+pub struct ShowRequest {
+    #[serde(alias = "name")]
+    pub model: String,
+    #[serde(default)]
+    pub verbose: bool,
+}
+
+/// The Ollama `options` forwarded upstream, the others are ignored
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct ChatOptions {
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub temperature: Option<f32>,
-    // Add other options as needed (e.g., top_p, n, etc.)
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// Maximum number of generated tokens, no limit if negative
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// Context window in tokens, the history is truncated to fit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u64>,
+}
+
+impl ChatOptions {
+    /// The options of a request sending them as a map, like `/api/generate`
+    pub fn from_map(options: &HashMap<String, Value>) -> serde_json::Result<Self> {
+        serde_json::to_value(options).and_then(serde_json::from_value)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
@@ -160,3 +163,57 @@ impl std::str::FromStr for ChatType {
         }
     }
 }
+
+/// Input of `/api/embed`, either a single text or a batch of texts
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum EmbedInput {
+    Single(String),
+    Batch(Vec<String>),
+}
+
+impl EmbedInput {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            EmbedInput::Single(text) => vec![text],
+            EmbedInput::Batch(texts) => texts,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EmbedRequest {
+    pub model: String,
+    pub input: EmbedInput,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncate: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<HashMap<String, Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EmbedResponse {
+    pub model: String,
+    pub embeddings: Vec<Vec<f32>>,
+    pub total_duration: u64,
+    pub load_duration: u64,
+    pub prompt_eval_count: u64,
+}
+
+/// Request of the legacy `/api/embeddings` endpoint
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EmbeddingsRequest {
+    pub model: String,
+    pub prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<HashMap<String, Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EmbeddingsResponse {
+    pub embedding: Vec<f32>,
+}
@@ -0,0 +1,55 @@
+/// OpenAI compatible structs, served under `/v1`
+/// https://github.com/ollama/ollama/blob/main/docs/openai.md
+use serde::{Deserialize, Serialize};
+
+use crate::structs::ollama::EmbedInput;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EmbeddingRequest {
+    pub model: String,
+    pub input: EmbedInput,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EmbeddingResponse {
+    #[serde(default)]
+    pub object: String,
+    pub data: Vec<EmbeddingData>,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub usage: EmbeddingUsage,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EmbeddingData {
+    #[serde(default)]
+    pub object: String,
+    pub embedding: Vec<f32>,
+    pub index: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct EmbeddingUsage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+/// Token usage of a completion, sent in the last chunk of a stream
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
@@ -0,0 +1,103 @@
+/// Recording of the exchanges of the models with `transcript = true`, one JSON
+/// object per line in `transcripts.jsonl` of the `[transcripts]` directory.
+/// The file is rotated to `transcripts.1.jsonl`, `transcripts.2.jsonl`, ...
+/// once it grows past `max_file_bytes`. Known API keys are masked.
+/// Entries are written in order on a blocking thread, never from the stream path.
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+use crate::logging::redact;
+use crate::structs::config::TranscriptConfig;
+
+#[derive(Default)]
+struct Queue {
+    entries: VecDeque<(TranscriptConfig, Value)>,
+    /// A thread is writing the entries, so lines and rotations don't interleave
+    writing: bool,
+}
+
+#[derive(Default)]
+pub struct Transcripts {
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl Transcripts {
+    pub fn record(&self, config: &TranscriptConfig, entry: &Value) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.entries.push_back((config.clone(), entry.clone()));
+        if queue.writing {
+            return;
+        }
+        queue.writing = true;
+        let shared = self.queue.clone();
+        tokio::task::spawn_blocking(move || write_queued(&shared));
+    }
+}
+
+/// Write the entries until none is left, without holding the lock
+fn write_queued(queue: &Mutex<Queue>) {
+    loop {
+        let next = {
+            let mut queue = queue.lock().unwrap();
+            let next = queue.entries.pop_front();
+            queue.writing = next.is_some();
+            next
+        };
+        let Some((config, entry)) = next else {
+            return;
+        };
+        if let Err(e) = write(&config, &entry) {
+            warn!("Failed to write transcript to {}: {}", config.dir, e);
+        }
+    }
+}
+
+fn write(config: &TranscriptConfig, entry: &Value) -> Result<()> {
+    let dir = Path::new(&config.dir);
+    fs::create_dir_all(dir)?;
+
+    let mut line = redact(&entry.to_string());
+    line.push('\n');
+
+    let current = file(dir, 0);
+    let size = fs::metadata(&current).map_or(0, |metadata| metadata.len());
+    if size > 0 && size + line.len() as u64 > config.max_file_bytes {
+        rotate(dir, config.max_files)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&current)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Shift every file up by one, dropping those past `max_files`
+fn rotate(dir: &Path, max_files: usize) -> Result<()> {
+    let oldest = file(dir, max_files.max(1));
+    if oldest.exists() {
+        fs::remove_file(&oldest)?;
+    }
+    for index in (0..max_files.max(1)).rev() {
+        let from = file(dir, index);
+        if from.exists() {
+            fs::rename(&from, file(dir, index + 1))?;
+        }
+    }
+    Ok(())
+}
+
+/// The current file for `index` 0, rotated files after it
+fn file(dir: &Path, index: usize) -> PathBuf {
+    match index {
+        0 => dir.join("transcripts.jsonl"),
+        index => dir.join(format!("transcripts.{}.jsonl", index)),
+    }
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use anyhow::Result;
+use common::{spawn_app, TestConfig};
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn test_bearer_auth() -> Result<()> {
+    let config = TestConfig::new(
+        r#"
+[[auth.clients]]
+name = "editor"
+key = "sk-editor"
+models = ["glm-4-plus"]
+
+[[auth.clients]]
+name = "ops"
+key = "sk-ops"
+models = ["*"]
+admin = true
+
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "http://127.0.0.1:9/chat/completions"
+api_key = ""
+"#,
+    )?;
+    let addr = spawn_app(config.state("glm-4-plus")).await?;
+    let client = Client::new();
+
+    let response = client.get(format!("{}/api/ping", addr)).send().await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let error: Value = response.json().await?;
+    assert_eq!(error["error"], "missing bearer token");
+
+    let response = client
+        .get(format!("{}/api/ping", addr))
+        .bearer_auth("sk-wrong")
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .get(format!("{}/api/ping", addr))
+        .bearer_auth("sk-editor")
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .post(format!("{}/api/embed", addr))
+        .bearer_auth("sk-editor")
+        .json(&json!({"model": "embedding-3", "input": "hi"}))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let error: Value = response.json().await?;
+    assert_eq!(
+        error["error"],
+        "client editor may not use model embedding-3"
+    );
+
+    let response = client
+        .get(format!("{}/admin/keys", addr))
+        .bearer_auth("sk-editor")
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client
+        .get(format!("{}/admin/keys", addr))
+        .bearer_auth("sk-ops")
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use anyhow::Result;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use common::{serve, TestConfig};
+use lumos::ollama::Balancer;
+use lumos::structs::config::{BalanceStrategy, Model};
+use std::time::{Duration, Instant};
+
+fn model(strategy: BalanceStrategy) -> Model {
+    let mut model: Model = toml::from_str(
+        r#"
+model_name = "Qwen2.5-72B-Instruct"
+provider = "xinference"
+api_key = ""
+endpoints = [
+    { url = "http://node-a/v1/chat/completions", weight = 2 },
+    { url = "http://node-b/v1/chat/completions" },
+]
+
+[balance]
+max_failures = 2
+"#,
+    )
+    .unwrap();
+    model.balance.strategy = strategy;
+    model
+}
+
+#[test]
+fn test_weighted_round_robin() {
+    let balancer = Balancer::default();
+    let model = model(BalanceStrategy::WeightedRoundRobin);
+
+    let picked = (0..6)
+        .map(|_| balancer.pick(&model).0)
+        .map(|url| url[7..13].to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        picked,
+        ["node-a", "node-b", "node-a", "node-a", "node-b", "node-a"]
+    );
+}
+
+#[test]
+fn test_least_in_flight() {
+    let balancer = Balancer::default();
+    let model = model(BalanceStrategy::LeastInFlight);
+
+    let (first, first_guard) = balancer.pick(&model);
+    let (second, _second_guard) = balancer.pick(&model);
+    assert_ne!(first, second);
+
+    // node-a has twice the weight so it takes a second request first
+    let (third, _third_guard) = balancer.pick(&model);
+    assert!(third.contains("node-a"));
+
+    drop(first_guard);
+    let (fourth, _) = balancer.pick(&model);
+    assert_eq!(fourth, first);
+}
+
+#[test]
+fn test_failing_endpoint_is_ejected() {
+    let balancer = Balancer::default();
+    let model = model(BalanceStrategy::WeightedRoundRobin);
+    let node_a = "http://node-a/v1/chat/completions";
+
+    balancer.report(&model, node_a, true);
+    balancer.report(&model, node_a, true);
+    for _ in 0..3 {
+        assert!(balancer.pick(&model).0.contains("node-b"));
+    }
+}
+
+/// A node answering its health check after half a second with `status`
+async fn slow_node(status: StatusCode) -> Result<String> {
+    let health = move || async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        status
+    };
+    serve(Router::new().route("/health", get(health))).await
+}
+
+#[tokio::test]
+async fn test_health_checks_run_concurrently() -> Result<()> {
+    let healthy = slow_node(StatusCode::OK).await?;
+    let failing = slow_node(StatusCode::INTERNAL_SERVER_ERROR).await?;
+    let config = TestConfig::new(&format!(
+        r#"
+[qwen25-72b]
+model_name = "Qwen2.5-72B-Instruct"
+provider = "xinference"
+api_key = ""
+endpoints = [
+    {{ url = "{healthy}/v1/chat/completions" }},
+    {{ url = "{failing}/v1/chat/completions" }},
+]
+
+[qwen25-72b.balance]
+health_check_path = "/health"
+"#
+    ))?
+    .load()?;
+    let balancer = Balancer::default();
+
+    let started = Instant::now();
+    balancer.check_health(&config).await;
+    assert!(started.elapsed() < Duration::from_millis(900));
+
+    let model = config.get_model("qwen25-72b").unwrap();
+    for _ in 0..3 {
+        assert!(balancer.pick(model).0.starts_with(&healthy));
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+mod common;
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::routing::post;
+use axum::Router;
+use common::{serve, spawn_app, TestConfig};
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+type Calls = Arc<AtomicUsize>;
+
+async fn completions(State(calls): State<Calls>) -> &'static str {
+    calls.fetch_add(1, Ordering::SeqCst);
+    concat!(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Hello\"}}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{\"content\":\" world\"}}]}\n\n",
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":2,\"total_tokens\":7}}\n\n",
+        "data: [DONE]\n\n"
+    )
+}
+
+async fn upstream(calls: Calls) -> Result<String> {
+    let app = Router::new()
+        .route("/chat/completions", post(completions))
+        .with_state(calls);
+    Ok(format!("{}/chat/completions", serve(app).await?))
+}
+
+/// Lines of the NDJSON response
+async fn chat(addr: &str, content: &str, temperature: f64) -> Result<Vec<Value>> {
+    let body = Client::new()
+        .post(format!("{}/api/chat", addr))
+        .json(&json!({
+            "model": "glm-4-plus",
+            "messages": [{ "role": "user", "content": content }],
+            "options": { "temperature": temperature }
+        }))
+        .send()
+        .await?
+        .text()
+        .await?;
+    Ok(body
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?)
+}
+
+fn contents(lines: &[Value]) -> Vec<&str> {
+    lines
+        .iter()
+        .filter_map(|line| line["message"]["content"].as_str())
+        .filter(|content| !content.is_empty())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_memory_cache() -> Result<()> {
+    let calls = Calls::default();
+    let url = upstream(calls.clone()).await?;
+    let config = TestConfig::new(&format!(
+        r#"
+[cache]
+enabled = true
+max_entries = 1
+
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "{url}"
+api_key = ""
+"#
+    ))?;
+    let addr = spawn_app(config.state("glm-4-plus")).await?;
+
+    let first = chat(&addr, "hi", 0.0).await?;
+    let cached = chat(&addr, "hi", 0.0).await?;
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(contents(&cached), vec!["Hello", " world"]);
+    assert_eq!(cached.len(), first.len());
+    let done = cached.last().unwrap();
+    assert_eq!(done["done"], true);
+    assert_eq!(done["model"], "glm:4-plus");
+    assert_eq!(done["eval_count"], 2);
+
+    // not deterministic, never cached
+    chat(&addr, "hi", 0.7).await?;
+    chat(&addr, "hi", 0.7).await?;
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    // evicts the least recently used entry
+    chat(&addr, "hello", 0.0).await?;
+    chat(&addr, "hi", 0.0).await?;
+    assert_eq!(calls.load(Ordering::SeqCst), 5);
+
+    let client = Client::new();
+    let stats: Value = client
+        .get(format!("{}/admin/cache", addr))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(
+        stats,
+        json!({ "enabled": true, "hits": 1, "misses": 3, "entries": 1 })
+    );
+    let metrics = client
+        .get(format!("{}/metrics", addr))
+        .send()
+        .await?
+        .text()
+        .await?;
+    assert!(metrics.contains("lumos_cache_hits_total{model=\"glm-4-plus\"} 1"));
+    assert!(metrics.contains("lumos_cache_misses_total{model=\"glm-4-plus\"} 3"));
+
+    let purged: Value = client
+        .delete(format!("{}/admin/cache", addr))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(purged["purged"], 1);
+    chat(&addr, "hi", 0.0).await?;
+    assert_eq!(calls.load(Ordering::SeqCst), 6);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_disk_cache() -> Result<()> {
+    let calls = Calls::default();
+    let url = upstream(calls.clone()).await?;
+    let config = TestConfig::empty()?;
+    let dir = config.file("cache");
+    config.write(&format!(
+        r#"
+[cache]
+enabled = true
+dir = "{}"
+
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "{url}"
+api_key = ""
+options = {{ temperature = 0.0 }}
+"#,
+        dir
+    ))?;
+
+    let addr = spawn_app(config.state("glm-4-plus")).await?;
+    chat(&addr, "hi", 0.0).await?;
+    assert_eq!(std::fs::read_dir(&dir)?.count(), 1);
+
+    // a new server finds the entries of the previous one
+    let addr = spawn_app(config.state("glm-4-plus")).await?;
+    let cached = chat(&addr, "hi", 0.0).await?;
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(contents(&cached), vec!["Hello", " world"]);
+
+    let generated: Value = Client::new()
+        .post(format!("{}/api/generate", addr))
+        .json(&json!({ "model": "glm-4-plus", "prompt": "hi" }))
+        .send()
+        .await?
+        .text()
+        .await?
+        .lines()
+        .next()
+        .map(serde_json::from_str)
+        .transpose()?
+        .unwrap();
+    assert_eq!(generated["response"], "Hello");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use anyhow::Result;
+use axum::body::Body;
+use axum::extract::State;
+use axum::routing::post;
+use axum::Router;
+use common::{serve, spawn_app, TestConfig};
+use futures_util::StreamExt;
+use reqwest::Client;
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Set once the upstream stops streaming
+type Stopped = Arc<AtomicBool>;
+
+struct Guard(Stopped);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Streams a first chunk and then hangs for a minute
+async fn completions(State(stopped): State<Stopped>) -> Body {
+    let guard = Guard(stopped);
+    Body::from_stream(async_stream::stream! {
+        let _guard = guard;
+        yield Ok::<_, std::io::Error>("data: {\"choices\":[{\"delta\":{\"content\":\"Hello\"}}]}\n\n");
+        // silent, a disconnect is only seen by reading the connection
+        tokio::time::sleep(Duration::from_secs(60)).await;
+    })
+}
+
+#[tokio::test]
+async fn test_client_disconnect_cancels_upstream() -> Result<()> {
+    let stopped = Stopped::default();
+    let upstream = serve(
+        Router::new()
+            .route("/chat/completions", post(completions))
+            .with_state(stopped.clone()),
+    )
+    .await?;
+
+    let config = TestConfig::new(&format!(
+        r#"
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "{upstream}/chat/completions"
+api_key = ""
+"#
+    ))?;
+    let state = config.state("glm-4-plus");
+    let addr = spawn_app(state.clone()).await?;
+
+    let response = Client::new()
+        .post(format!("{}/api/chat", addr))
+        .json(&json!({
+            "model": "glm-4-plus",
+            "messages": [{ "role": "user", "content": "hi" }]
+        }))
+        .send()
+        .await?;
+    let mut body = response.bytes_stream();
+    let first = body.next().await.unwrap()?;
+    assert!(String::from_utf8_lossy(&first).contains("Hello"));
+    drop(body);
+
+    for _ in 0..40 {
+        if stopped.load(Ordering::SeqCst) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(stopped.load(Ordering::SeqCst), "upstream still streaming");
+
+    let metrics = state.metrics.render();
+    assert!(metrics.contains("lumos_cancelled_total{model=\"glm-4-plus\"} 1"));
+    assert!(metrics.contains("lumos_streams_in_flight{model=\"glm-4-plus\",provider=\"zhipu\"} 0"));
+
+    Ok(())
+}
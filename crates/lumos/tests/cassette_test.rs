@@ -0,0 +1,89 @@
+mod common;
+
+use anyhow::Result;
+use axum::routing::post;
+use axum::Router;
+use common::{body, message, serve, TestConfig};
+use lumos::ollama::{cassette_path, dispatch, Dispatch};
+use lumos::structs::ollama::ChatType;
+use serde_json::{json, Value};
+
+const UPSTREAM_BODY: &str = concat!(
+    "data: {\"choices\":[{\"delta\":{\"content\":\"北京\"}}]}\n\n",
+    "data: {\"choices\":[{\"delta\":{\"content\":\" is the capital\"}}]}\n\n",
+    "data: [DONE]\n\n"
+);
+
+async fn completions() -> &'static str {
+    UPSTREAM_BODY
+}
+
+fn write_config(provider: &str, upstream: &str, dir: &str) -> Result<TestConfig> {
+    TestConfig::new(&format!(
+        r#"
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "{provider}"
+url = "{upstream}/chat/completions"
+api_key = ""
+cassettes = "{dir}"
+"#
+    ))
+}
+
+async fn chat(test_config: &TestConfig, prompt: &str) -> Result<String> {
+    let config = test_config.load()?;
+    let ctx = Dispatch::new(
+        test_config.state(""),
+        &config,
+        "glm-4-plus".to_string(),
+        None,
+    );
+    let response = dispatch(ctx, vec![message("user", prompt)], ChatType::Chat, None).await?;
+    Ok(body(response).await)
+}
+
+/// The `message.content` of the streamed chunks
+fn contents(body: &str) -> Vec<String> {
+    body.lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|chunk| chunk["message"]["content"].as_str().map(String::from))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_record_and_replay() -> Result<()> {
+    let upstream = serve(Router::new().route("/chat/completions", post(completions))).await?;
+
+    let cassettes = tempfile::tempdir()?;
+    let dir = cassettes.path().to_string_lossy().into_owned();
+
+    let record = write_config("zhipu", &upstream, &dir)?;
+    let recorded = chat(&record, "Where is the capital of China?").await?;
+
+    let config = record.load()?;
+    let request_body = json!({
+        "model": "glm-4-plus",
+        "messages": [{"role": "user", "content": "Where is the capital of China?"}],
+        "stream": true,
+        "stream_options": { "include_usage": true }
+    });
+    let path = cassette_path(&dir, config.get_model("glm-4-plus").unwrap(), &request_body);
+    let cassette: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    assert_eq!(cassette["status"], 200);
+    assert_eq!(cassette["body"], UPSTREAM_BODY);
+
+    // the upstream is not used anymore
+    let replay = write_config("replay", "http://127.0.0.1:9", &dir)?;
+    let replayed = chat(&replay, "Where is the capital of China?").await?;
+    assert_eq!(contents(&replayed), contents(&recorded));
+    assert_eq!(contents(&replayed)[0], "北京");
+
+    let error = chat(&replay, "Who is the boss of SpaceX?")
+        .await
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("record it first"));
+
+    Ok(())
+}
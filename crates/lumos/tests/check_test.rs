@@ -0,0 +1,125 @@
+use lumos::check::{check, Severity};
+
+/// `(line, message)` of the errors
+fn errors(contents: &str) -> Vec<(usize, String)> {
+    check(contents)
+        .into_iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .map(|diagnostic| (diagnostic.line, diagnostic.message))
+        .collect()
+}
+
+#[test]
+fn test_valid_config() {
+    let diagnostics = check(
+        r#"
+[accounting]
+costs_file = "costs.json"
+
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "https://open.bigmodel.cn/api/paas/v4/chat/completions"
+api_key = ["sk-one", "sk-two"]
+fallbacks = ["deepseek-chat"]
+
+[glm-4-plus.retry]
+max_attempts = 2
+
+[deepseek-chat]
+model_name = "deepseek-chat"
+provider = "deepseek"
+api_key = "sk-three"
+endpoints = [{ url = "https://api.deepseek.com/chat/completions", weight = 2 }]
+
+[mock]
+model_name = "mock"
+provider = "mock"
+api_key = ""
+"#,
+    );
+    assert_eq!(diagnostics, vec![]);
+}
+
+#[test]
+fn test_problems_with_lines() {
+    let errors = errors(
+        r#"[auth]
+usage_fil = "usage.json"
+
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "https://open.bigmodel.cn/api/paas/v4/chat/completions"
+api_key = ["sk-one", ""]
+fallbacks = ["nope"]
+temprature = 0.3
+
+["GLM-4-Plus:latest"]
+model_name = "glm-4-plus"
+provider = "openai"
+url = "ftp://example.com"
+api_key = "sk-four"
+
+[deepseek-chat]
+model_name = "deepseek-chat"
+provider = "deepseek"
+url = "not a url"
+api_key = ""
+dimensions = "big"
+endpoints = [{ url = "https://api.deepseek.com", wieght = 2 }]
+"#,
+    );
+    let lines = errors.iter().map(|(line, _)| *line).collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        vec![2, 8, 9, 10, 12, 14, 15, 21, 22, 23, 24],
+        "{:?}",
+        errors
+    );
+
+    let message = |line: usize| {
+        errors
+            .iter()
+            .find(|(error_line, _)| *error_line == line)
+            .map(|(_, message)| message.as_str())
+            .unwrap()
+    };
+    assert!(message(2).contains("unknown field usage_fil"));
+    assert_eq!(message(8), "glm-4-plus.api_key[1] is empty");
+    assert_eq!(message(9), "glm-4-plus falls back to unknown model nope");
+    assert!(message(10).contains("unknown field temprature"));
+    assert!(message(12).contains("same as glm-4-plus on line 4"));
+    assert!(message(14).contains("unknown variant `openai`"));
+    assert!(message(15).contains("http(s) URL"));
+    assert!(message(21).contains("not a valid URL"));
+    assert_eq!(message(22), "deepseek-chat.api_key is empty");
+    assert!(message(23).starts_with("deepseek-chat.dimensions: invalid type"));
+    assert!(message(24).contains("unknown field wieght in deepseek-chat.endpoints[0]"));
+}
+
+#[test]
+fn test_syntax_and_reference_errors() {
+    assert_eq!(
+        errors("[glm-4-plus]\nmodel_name = \n"),
+        vec![(2, "invalid string, expected `\"`, `'`".to_string())]
+    );
+
+    let errors = errors(
+        r#"
+[xinference]
+model_name = "qwen"
+provider = "xinference"
+url = "${LUMOS_CHECK_UNSET_URL}"
+api_key = ""
+"#,
+    );
+    assert_eq!(
+        errors,
+        vec![(
+            5,
+            "environment variable LUMOS_CHECK_UNSET_URL referenced by xinference.url is not set"
+                .to_string()
+        )]
+    );
+}
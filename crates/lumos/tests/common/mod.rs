@@ -0,0 +1,98 @@
+//! Fixtures shared by the integration tests. Every test gets a directory of
+//! its own for its config and the files the proxy writes, so tests and
+//! concurrent `cargo test` runs don't step on each other.
+#![allow(dead_code)]
+
+use anyhow::Result;
+use axum::response::IntoResponse;
+use axum::Router;
+use futures_util::StreamExt;
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+use lumos::config::Config;
+use lumos::structs::app::AppState;
+use lumos::structs::ollama::Message;
+
+/// A config file in a temporary directory, removed with it
+pub struct TestConfig {
+    pub dir: TempDir,
+    pub path: String,
+}
+
+impl TestConfig {
+    /// An empty config, for configs pointing at files of the directory
+    pub fn empty() -> Result<Self> {
+        let dir = tempfile::tempdir()?;
+        let path = dir
+            .path()
+            .join("config.toml")
+            .to_string_lossy()
+            .into_owned();
+        Ok(TestConfig { dir, path })
+    }
+
+    pub fn new(contents: &str) -> Result<Self> {
+        let config = TestConfig::empty()?;
+        config.write(contents)?;
+        Ok(config)
+    }
+
+    pub fn write(&self, contents: &str) -> Result<()> {
+        std::fs::write(&self.path, contents)?;
+        Ok(())
+    }
+
+    /// Path of a file or directory next to the config
+    pub fn file(&self, name: &str) -> String {
+        self.dir.path().join(name).to_string_lossy().into_owned()
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn load(&self) -> Result<Config> {
+        Config::from_file(&self.path)
+    }
+
+    /// State of a proxy serving `model_name` with this config
+    pub fn state(&self, model_name: &str) -> Arc<AppState> {
+        Arc::new(AppState::new(model_name.to_string(), self.path.clone()))
+    }
+}
+
+/// Serve the router on a free port, returns its `http://` address
+pub async fn serve(router: Router) -> Result<String> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.unwrap();
+    });
+    Ok(format!("http://{}", addr))
+}
+
+/// Serve the proxy with the state, returns its `http://` address
+pub async fn spawn_app(state: Arc<AppState>) -> Result<String> {
+    serve(lumos::app::create_app(state).await).await
+}
+
+pub fn message(role: &str, content: &str) -> Message {
+    Message {
+        role: role.to_string(),
+        content: content.to_string(),
+        ..Default::default()
+    }
+}
+
+/// The whole body of a response, e.g. of `dispatch`
+pub async fn body(response: impl IntoResponse) -> String {
+    response
+        .into_response()
+        .into_body()
+        .into_data_stream()
+        .map(|chunk| String::from_utf8_lossy(&chunk.unwrap()).into_owned())
+        .collect::<String>()
+        .await
+}
@@ -0,0 +1,53 @@
+use lumos::ollama::ContextStore;
+use lumos::structs::ollama::Message;
+
+#[test]
+fn test_context_store() {
+    let store = ContextStore::default();
+    let turns = vec![
+        Message {
+            role: "user".to_string(),
+            content: "My name is Lumos.".to_string(),
+            ..Default::default()
+        },
+        Message {
+            role: "assistant".to_string(),
+            content: "Hello Lumos!".to_string(),
+            ..Default::default()
+        },
+    ];
+
+    let handle = store.insert(turns);
+    let history = store.get(&handle);
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[1].content, "Hello Lumos!");
+
+    // a new turn gets a new handle, the old one stays valid
+    let next = store.insert(history);
+    assert_ne!(handle, next);
+    assert_eq!(store.get(&handle).len(), 2);
+
+    assert!(store.get(&[1, 2, 3]).is_empty());
+}
+
+#[test]
+fn test_least_recently_used_are_evicted() {
+    let store = ContextStore::new(2);
+    let turn = |content: &str| {
+        vec![Message {
+            role: "user".to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        }]
+    };
+
+    let first = store.insert(turn("first"));
+    let second = store.insert(turn("second"));
+    // used again, so the second goes instead
+    assert_eq!(store.get(&first).len(), 1);
+    let third = store.insert(turn("third"));
+
+    assert_eq!(store.get(&first)[0].content, "first");
+    assert!(store.get(&second).is_empty());
+    assert_eq!(store.get(&third)[0].content, "third");
+}
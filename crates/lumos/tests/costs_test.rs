@@ -0,0 +1,101 @@
+mod common;
+
+use anyhow::Result;
+use axum::routing::post;
+use axum::Router;
+use common::{body, message, serve, TestConfig};
+use lumos::costs::{self, CostRecord, GroupBy};
+use lumos::ollama::{dispatch, Dispatch};
+use lumos::structs::ollama::ChatType;
+
+async fn completions() -> &'static str {
+    concat!(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Beijing\"}}]}\n\n",
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":1000,\"completion_tokens\":500,\"total_tokens\":1500}}\n\n",
+        "data: [DONE]\n\n"
+    )
+}
+
+#[tokio::test]
+async fn test_cost_of_request() -> Result<()> {
+    let upstream = serve(Router::new().route("/chat/completions", post(completions))).await?;
+
+    let test_config = TestConfig::empty()?;
+    let costs_file = test_config.file("costs.json");
+    test_config.write(&format!(
+        r#"
+[accounting]
+costs_file = "{costs_file}"
+
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "{upstream}/chat/completions"
+api_key = ""
+pricing = {{ input = 5.0, output = 10.0 }}
+"#
+    ))?;
+    let config = test_config.load()?;
+    let state = test_config.state("");
+
+    for _ in 0..2 {
+        let messages = vec![message("user", "Where is the capital of China?")];
+        let ctx = Dispatch::new(state.clone(), &config, "glm-4-plus".to_string(), None);
+        body(dispatch(ctx, messages, ChatType::Chat, None).await?).await;
+    }
+
+    // saved in the background, once both requests are in
+    let mut records = costs::load(&costs_file)?;
+    for _ in 0..40 {
+        if records.first().is_some_and(|record| record.requests == 2) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        records = costs::load(&costs_file)?;
+    }
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].client, "anonymous");
+    assert_eq!(records[0].currency, "CNY");
+    assert_eq!(records[0].requests, 2);
+    assert_eq!(records[0].input_tokens, 2000);
+    assert!((records[0].cost - 0.02).abs() < 1e-9);
+
+    Ok(())
+}
+
+#[test]
+fn test_report() {
+    let record = |day: &str, client: &str, model: &str, cost: f64| CostRecord {
+        day: day.to_string(),
+        client: client.to_string(),
+        model: model.to_string(),
+        currency: "CNY".to_string(),
+        requests: 1,
+        input_tokens: 10,
+        output_tokens: 20,
+        cost,
+    };
+    let records = vec![
+        record("2024-11-01", "editor", "glm-4-plus", 0.5),
+        record("2024-11-01", "ops", "deepseek-chat", 0.25),
+        record("2024-11-02", "editor", "deepseek-chat", 0.25),
+    ];
+
+    let by_model = costs::report(&records, GroupBy::Model);
+    assert_eq!(by_model.len(), 2);
+    assert_eq!(by_model[0].key, "deepseek-chat");
+    assert_eq!(by_model[0].requests, 2);
+    assert_eq!(by_model[0].cost, 0.5);
+
+    let by_client = costs::report(&records, GroupBy::Client);
+    assert_eq!(by_client[0].key, "editor");
+    assert_eq!(by_client[0].cost, 0.75);
+
+    let csv = costs::to_csv(&costs::report(&records, GroupBy::Day), GroupBy::Day);
+    assert_eq!(
+        csv,
+        "day,currency,requests,input_tokens,output_tokens,cost\n\
+         2024-11-01,CNY,2,20,40,0.750000\n\
+         2024-11-02,CNY,1,10,20,0.250000\n"
+    );
+}
@@ -4,16 +4,22 @@ use axum::response::IntoResponse;
 use axum::response::Response;
 use futures_util::StreamExt;
 use lumos::config::Config;
-use lumos::ollama::dispatch;
+use lumos::ollama::{dispatch, Dispatch};
+use lumos::structs::app::AppState;
 use lumos::structs::ollama::ChatType;
 use lumos::structs::ollama::{ChatRequest, Message};
+use std::sync::Arc;
 
 #[tokio::test]
 async fn test_dispatch() -> Result<(), Box<dyn std::error::Error>> {
-    let config_path = "keys.toml";
+    let config_path = "tests/cassettes/replay.toml";
+    let state = Arc::new(AppState::new(
+        "deepseek-chat".to_string(),
+        config_path.to_string(),
+    ));
     let config = Config::from_file(config_path)
         .context("无法加载配置文件")
-        .map_err(|e| axum::Error::new(e))?;
+        .map_err(axum::Error::new)?;
 
     let test_cases = vec![
         ("deepseek-chat", "Where is the capital of China?", "Beijing"),
@@ -21,7 +27,7 @@ async fn test_dispatch() -> Result<(), Box<dyn std::error::Error>> {
     ];
 
     for (model_name, prompt, expected_substring) in test_cases {
-        let provider = config
+        config
             .models()
             .get(model_name)
             .context(format!("未找到模型提供者: {}", model_name))?;
@@ -38,10 +44,15 @@ async fn test_dispatch() -> Result<(), Box<dyn std::error::Error>> {
             ..Default::default()
         };
 
-        let response: Response<Body> = dispatch(model_name, req.messages, provider, ChatType::Chat)
-            .await
-            .map_err(|e| axum::Error::new(e))?
-            .into_response();
+        let response: Response<Body> = dispatch(
+            Dispatch::new(state.clone(), &config, model_name.to_string(), None),
+            req.messages,
+            ChatType::Chat,
+            None,
+        )
+        .await
+        .map_err(axum::Error::new)?
+        .into_response();
         let mut stream = response.into_body().into_data_stream();
 
         let mut collected_chunks = Vec::new();
@@ -71,7 +82,7 @@ async fn test_dispatch() -> Result<(), Box<dyn std::error::Error>> {
             reply_string
         );
 
-        assert!(collected_chunks.len() > 0);
+        assert!(!collected_chunks.is_empty());
     }
 
     Ok(())
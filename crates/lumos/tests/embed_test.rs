@@ -0,0 +1,137 @@
+mod common;
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use common::{serve, spawn_app, TestConfig};
+use lumos::structs::ollama::{EmbedInput, EmbedRequest};
+use lumos::structs::openai::EmbeddingResponse;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+type Received = Arc<Mutex<Vec<Value>>>;
+
+/// Embeds the n-th input as `[n]`, listed in reverse order
+async fn embeddings(State(received): State<Received>, Json(body): Json<Value>) -> Json<Value> {
+    let inputs = body["input"].as_array().map_or(0, Vec::len);
+    received.lock().unwrap().push(body);
+    let data = (0..inputs)
+        .rev()
+        .map(|index| json!({ "embedding": [index as f32], "index": index }))
+        .collect::<Vec<_>>();
+    Json(json!({
+        "data": data,
+        "model": "embedding-3",
+        "usage": { "prompt_tokens": 3, "total_tokens": 3 }
+    }))
+}
+
+#[test]
+fn test_embed_input() -> Result<()> {
+    let single: EmbedRequest = serde_json::from_str(r#"{"model": "embedding-3", "input": "hi"}"#)?;
+    assert_eq!(single.input.into_vec(), vec!["hi"]);
+
+    let batch: EmbedRequest =
+        serde_json::from_str(r#"{"model": "embedding-3", "input": ["hi", "there"]}"#)?;
+    assert!(matches!(batch.input, EmbedInput::Batch(ref texts) if texts.len() == 2));
+
+    Ok(())
+}
+
+#[test]
+fn test_embedding_response_from_upstream() -> Result<()> {
+    // Xinference omits `object`, Zhipu adds `completion_tokens`
+    let upstream = r#"{
+        "data": [{"embedding": [0.1, 0.2], "index": 0}],
+        "model": "bge-m3",
+        "usage": {"prompt_tokens": 3, "completion_tokens": 0, "total_tokens": 3}
+    }"#;
+    let response: EmbeddingResponse = serde_json::from_str(upstream)?;
+    assert_eq!(response.data[0].embedding, vec![0.1, 0.2]);
+    assert_eq!(response.usage.prompt_tokens, 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_embed_endpoints() -> Result<()> {
+    let received = Received::default();
+    let upstream = serve(
+        Router::new()
+            .route("/embeddings", post(embeddings))
+            .with_state(received.clone()),
+    )
+    .await?;
+    let config = TestConfig::new(&format!(
+        r#"
+[embedding-3]
+model_name = "embedding-3"
+provider = "zhipu"
+url = "{upstream}/embeddings"
+api_key = ""
+dimensions = 256
+
+[bge-m3]
+model_name = "bge-m3"
+provider = "xinference"
+url = "{upstream}/embeddings"
+api_key = ""
+"#
+    ))?;
+    let addr = spawn_app(config.state("glm-4-plus")).await?;
+    let client = Client::new();
+    let post = |path: &str, request: Value| {
+        client
+            .post(format!("{}{}", addr, path))
+            .json(&request)
+            .send()
+    };
+
+    // in the order of the input, with the dimensions of the config
+    let response: Value = post(
+        "/api/embed",
+        json!({ "model": "embedding-3", "input": ["one", "two", "three"] }),
+    )
+    .await?
+    .json()
+    .await?;
+    assert_eq!(response["model"], "embedding-3");
+    assert_eq!(response["embeddings"], json!([[0.0], [1.0], [2.0]]));
+    assert_eq!(response["prompt_eval_count"], 3);
+    assert_eq!(received.lock().unwrap()[0]["dimensions"], 256);
+
+    let response: Value = post(
+        "/api/embeddings",
+        json!({ "model": "embedding-3", "prompt": "one" }),
+    )
+    .await?
+    .json()
+    .await?;
+    assert_eq!(response, json!({ "embedding": [0.0] }));
+    assert_eq!(received.lock().unwrap()[1]["input"], json!(["one"]));
+
+    // the dimensions of the request win
+    let response: Value = post(
+        "/v1/embeddings",
+        json!({ "model": "bge-m3", "input": ["one", "two"], "dimensions": 512 }),
+    )
+    .await?
+    .json()
+    .await?;
+    assert_eq!(response["object"], "list");
+    assert_eq!(response["model"], "bge-m3");
+    assert_eq!(
+        response["data"],
+        json!([
+            { "object": "embedding", "embedding": [0.0], "index": 0 },
+            { "object": "embedding", "embedding": [1.0], "index": 1 },
+        ])
+    );
+    let received = received.lock().unwrap();
+    assert_eq!(received[2]["model"], "bge-m3");
+    assert_eq!(received[2]["dimensions"], 512);
+
+    Ok(())
+}
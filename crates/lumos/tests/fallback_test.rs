@@ -0,0 +1,132 @@
+mod common;
+
+use anyhow::Result;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::Router;
+use common::{body, message, serve, TestConfig};
+use lumos::ollama::{dispatch, Dispatch};
+use lumos::structs::ollama::ChatType;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static FLAKY_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+async fn unavailable() -> impl IntoResponse {
+    (StatusCode::SERVICE_UNAVAILABLE, "overloaded")
+}
+
+/// Rate limited on the first request
+async fn flaky() -> axum::response::Response {
+    if FLAKY_CALLS.fetch_add(1, Ordering::SeqCst) == 0 {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [("retry-after", "0")],
+            "slow down",
+        )
+            .into_response();
+    }
+    completions().await.into_response()
+}
+
+async fn completions() -> impl IntoResponse {
+    "data: {\"choices\":[{\"delta\":{\"content\":\"Beijing\"}}]}\n\ndata: [DONE]\n\n"
+}
+
+/// Serve a failing and a working OpenAI compatible endpoint on a random port
+async fn spawn_upstream() -> Result<String> {
+    let app = Router::new()
+        .route("/down/chat/completions", post(unavailable))
+        .route("/up/chat/completions", post(completions))
+        .route("/flaky/chat/completions", post(flaky));
+    serve(app).await
+}
+
+#[tokio::test]
+async fn test_fallback_on_503() -> Result<()> {
+    let upstream = spawn_upstream().await?;
+    let test_config = TestConfig::new(&format!(
+        r#"
+[deepseek-chat]
+model_name = "deepseek-chat"
+provider = "deepseek"
+url = "{upstream}/down/chat/completions"
+api_key = ""
+fallbacks = ["missing", "glm-4-plus"]
+
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "{upstream}/up/chat/completions"
+api_key = ""
+
+[glm-4-long]
+model_name = "glm-4-long"
+provider = "zhipu"
+url = "{upstream}/down/chat/completions"
+api_key = ""
+"#
+    ))?;
+    let config = test_config.load()?;
+    let state = test_config.state("");
+
+    let messages = vec![message("user", "Where is the capital of China?")];
+    let response = dispatch(
+        Dispatch::new(state.clone(), &config, "deepseek-chat".to_string(), None),
+        messages.clone(),
+        ChatType::Chat,
+        None,
+    )
+    .await?;
+    let body = body(response).await;
+    assert!(body.contains("Beijing"));
+    assert!(body.contains("\"done\":true"));
+
+    // without fallbacks the upstream error is returned
+    let error = dispatch(
+        Dispatch::new(state.clone(), &config, "glm-4-long".to_string(), None),
+        messages,
+        ChatType::Chat,
+        None,
+    )
+    .await
+    .err()
+    .unwrap();
+    assert!(error.to_string().contains("503"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_retry_on_429() -> Result<()> {
+    let upstream = spawn_upstream().await?;
+    let test_config = TestConfig::new(&format!(
+        r#"
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "{upstream}/flaky/chat/completions"
+api_key = ""
+
+[glm-4-plus.retry]
+max_attempts = 2
+base_delay_ms = 10
+"#
+    ))?;
+    let config = test_config.load()?;
+    let state = test_config.state("");
+
+    let messages = vec![message("user", "Where is the capital of China?")];
+    let response = dispatch(
+        Dispatch::new(state.clone(), &config, "glm-4-plus".to_string(), None),
+        messages,
+        ChatType::Chat,
+        None,
+    )
+    .await?
+    .into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(FLAKY_CALLS.load(Ordering::SeqCst), 2);
+
+    Ok(())
+}
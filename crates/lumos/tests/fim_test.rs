@@ -0,0 +1,110 @@
+mod common;
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use common::{serve, spawn_app, TestConfig};
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+type Received = Arc<Mutex<Vec<Value>>>;
+
+async fn completions(State(received): State<Received>, Json(body): Json<Value>) -> &'static str {
+    received.lock().unwrap().push(body);
+    "data: {\"choices\":[{\"index\":0,\"text\":\"a + b\"}]}\n\ndata: [DONE]\n\n"
+}
+
+/// Ask the model to fill in the body of `add`, returns the status and the answer
+async fn fill(config: &TestConfig, model: &str) -> Result<(StatusCode, String)> {
+    let addr = spawn_app(config.state(model)).await?;
+    let response = Client::new()
+        .post(format!("{}/api/generate", addr))
+        .json(&json!({
+            "model": model,
+            "prompt": "fn add(a: i32, b: i32) -> i32 {\n    ",
+            "suffix": "\n}\n// {prefix}"
+        }))
+        .send()
+        .await?;
+    let status = response.status();
+    let answer = response
+        .text()
+        .await?
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .map(|line| match line["response"].as_str() {
+            Some(response) => response.to_string(),
+            None => line["error"].as_str().unwrap_or_default().to_string(),
+        })
+        .collect();
+    Ok((status, answer))
+}
+
+#[tokio::test]
+async fn test_fill_in_the_middle() -> Result<()> {
+    let received = Received::default();
+    let upstream = serve(
+        Router::new()
+            .route("/completions", post(completions))
+            .with_state(received.clone()),
+    )
+    .await?;
+    let config = TestConfig::new(&format!(
+        r#"
+[qwen-coder]
+model_name = "qwen2.5-coder"
+provider = "xinference"
+url = "{upstream}/chat/completions"
+completion_url = "{upstream}/completions"
+fim_template = "<|fim_prefix|>{{prefix}}<|fim_suffix|>{{suffix}}<|fim_middle|>"
+fim_stop = ["<|endoftext|>"]
+api_key = ""
+
+[deepseek-coder]
+model_name = "deepseek-chat"
+provider = "deepseek"
+url = "{upstream}/chat/completions"
+completion_url = "{upstream}/completions"
+api_key = ""
+
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "{upstream}/chat/completions"
+api_key = ""
+"#
+    ))?;
+
+    // the template, filled in once: the placeholder in the suffix is kept
+    let (status, answer) = fill(&config, "qwen-coder").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(answer.starts_with("a + b"));
+    let request = received.lock().unwrap()[0].clone();
+    assert_eq!(
+        request["prompt"],
+        "<|fim_prefix|>fn add(a: i32, b: i32) -> i32 {\n    <|fim_suffix|>\n}\n// {prefix}<|fim_middle|>"
+    );
+    assert!(request.get("suffix").is_none());
+    assert_eq!(request["stop"], json!(["<|endoftext|>"]));
+
+    // the native suffix of the endpoint
+    let (status, answer) = fill(&config, "deepseek-coder").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(answer.starts_with("a + b"));
+    let request = received.lock().unwrap()[1].clone();
+    assert_eq!(request["prompt"], "fn add(a: i32, b: i32) -> i32 {\n    ");
+    assert_eq!(request["suffix"], "\n}\n// {prefix}");
+
+    // neither
+    let (status, answer) = fill(&config, "glm-4-plus").await?;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(
+        answer,
+        "Model glm-4-plus does not support fill-in-the-middle"
+    );
+    assert_eq!(received.lock().unwrap().len(), 2);
+
+    Ok(())
+}
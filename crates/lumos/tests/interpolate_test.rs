@@ -0,0 +1,130 @@
+mod common;
+
+use anyhow::Result;
+use common::TestConfig;
+use lumos::logging::redact;
+
+#[test]
+fn test_env_and_file_references() -> Result<()> {
+    std::env::set_var("LUMOS_TEST_ZHIPU_KEY", "sk-from-the-environment");
+    std::env::set_var("LUMOS_TEST_HOST", "open.bigmodel.cn");
+    std::env::set_var("LUMOS_TEST_EMPTY", "");
+    let test_config = TestConfig::empty()?;
+    let secret_file = test_config.file("secret");
+    std::fs::write(&secret_file, "sk-from-a-secret-file\n")?;
+
+    test_config.write(&format!(
+        r#"
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "https://${{LUMOS_TEST_HOST}}/api/paas/v4/chat/completions"
+api_key = ["${{LUMOS_TEST_ZHIPU_KEY}}", "file:{secret}"]
+fim_template = "$${{LUMOS_TEST_HOST}} stays"
+
+[deepseek-chat]
+model_name = "${{LUMOS_TEST_UNSET_MODEL:-deepseek-chat}}"
+provider = "deepseek"
+url = "${{LUMOS_TEST_EMPTY:-https://api.deepseek.com/chat/completions}}"
+api_key = ""
+"#,
+        secret = secret_file
+    ))?;
+    let config = test_config.load()?;
+
+    let glm = config.get_model("glm-4-plus").unwrap();
+    assert_eq!(
+        glm.url,
+        "https://open.bigmodel.cn/api/paas/v4/chat/completions"
+    );
+    assert_eq!(
+        glm.api_key.keys(),
+        ["sk-from-the-environment", "sk-from-a-secret-file"]
+    );
+    assert_eq!(
+        glm.fim_template.as_deref(),
+        Some("${LUMOS_TEST_HOST} stays")
+    );
+
+    // only keys are masked in the logs
+    assert_eq!(redact("open.bigmodel.cn"), "open.bigmodel.cn");
+    assert_ne!(redact("sk-from-the-environment"), "sk-from-the-environment");
+    assert_ne!(redact("sk-from-a-secret-file"), "sk-from-a-secret-file");
+
+    let deepseek = config.get_model("deepseek-chat").unwrap();
+    assert_eq!(deepseek.model_name, "deepseek-chat");
+    assert_eq!(deepseek.url, "https://api.deepseek.com/chat/completions");
+
+    Ok(())
+}
+
+#[test]
+fn test_reference_errors() -> Result<()> {
+    let test_config = TestConfig::new(
+        r#"
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "https://open.bigmodel.cn/api/paas/v4/chat/completions"
+api_key = "${LUMOS_TEST_MISSING_KEY}"
+"#,
+    )?;
+    let error = test_config.load().err().unwrap().to_string();
+    assert_eq!(
+        error,
+        "environment variable LUMOS_TEST_MISSING_KEY referenced by glm-4-plus.api_key is not set"
+    );
+
+    let test_config = TestConfig::new(
+        r#"
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+api_key = "file:/nonexistent/lumos/secret"
+"#,
+    )?;
+    let error = test_config.load().err().unwrap().to_string();
+    assert!(error.contains("/nonexistent/lumos/secret"));
+    assert!(error.contains("glm-4-plus.api_key"));
+
+    // a secret file in the wrong place is not printed, other values are
+    let test_config = TestConfig::empty()?;
+    let secret_file = test_config.file("secret");
+    std::fs::write(&secret_file, "sk-misplaced-secret-value")?;
+    test_config.write(&format!(
+        r#"
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "file:{secret_file}"
+api_key = ""
+"#
+    ))?;
+    let error = test_config.load().err().unwrap().to_string();
+    assert!(!error.contains("sk-misplaced-secret-value"), "{}", error);
+
+    std::env::set_var("LUMOS_TEST_PROVIDER", "zhipu-cn");
+    let test_config = TestConfig::new(
+        r#"
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "${LUMOS_TEST_PROVIDER}"
+api_key = ""
+"#,
+    )?;
+    let error = test_config.load().err().unwrap().to_string();
+    assert!(error.contains("zhipu-cn"), "{}", error);
+
+    // errors of the file keep their line and column
+    let test_config = TestConfig::new(
+        r#"
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu-cn"
+api_key = ""
+"#,
+    )?;
+    let error = test_config.load().err().unwrap().to_string();
+    assert!(error.contains("line 4, column 12"), "{}", error);
+
+    Ok(())
+}
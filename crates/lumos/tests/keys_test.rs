@@ -0,0 +1,81 @@
+mod common;
+
+use common::TestConfig;
+use lumos::check::check;
+use lumos::ollama::{mask_key, KeyPool};
+use lumos::structs::config::{ApiKey, KeyRotation, Model, ProviderName};
+use std::time::Duration;
+
+fn model(keys: &[&str], key_rotation: KeyRotation) -> Model {
+    let config = format!(
+        r#"
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "https://open.bigmodel.cn/api/paas/v4/chat/completions"
+api_key = {:?}
+"#,
+        keys
+    );
+    let mut model: Model = toml::from_str(&config).unwrap();
+    assert_eq!(model.provider, ProviderName::Zhipu);
+    model.key_rotation = key_rotation;
+    model
+}
+
+#[test]
+fn test_round_robin_skips_benched_keys() {
+    let pool = KeyPool::default();
+    let model = model(&["key-a", "key-b", "key-c"], KeyRotation::RoundRobin);
+    assert!(matches!(model.api_key, ApiKey::Many(_)));
+
+    assert_eq!(pool.pick("glm", &model), "key-a");
+    assert_eq!(pool.pick("glm", &model), "key-b");
+
+    pool.report("key-c", 429, None);
+    assert_eq!(pool.pick("glm", &model), "key-a");
+
+    pool.report("key-a", 401, None);
+    assert_eq!(pool.pick("glm", &model), "key-b");
+    assert_eq!(pool.pick("glm", &model), "key-b");
+
+    let report = pool.report_model(&model);
+    assert_eq!(report[0].state, "benched");
+    assert_eq!(report[1].state, "active");
+    assert_eq!(report[1].requests, 3);
+}
+
+#[test]
+fn test_benched_keys_are_restored() {
+    let pool = KeyPool::default();
+    let model = model(&["key-a", "key-b"], KeyRotation::LeastRecentlyLimited);
+
+    pool.report("key-a", 429, Some(Duration::ZERO));
+    pool.report("key-b", 429, Some(Duration::from_secs(60)));
+
+    // key-a is back and key-b is still benched
+    assert_eq!(pool.pick("glm", &model), "key-a");
+    assert_eq!(pool.pick("glm", &model), "key-a");
+}
+
+#[test]
+fn test_mask_key() {
+    assert_eq!(mask_key("sk-1234567890abcdef"), "sk-...cdef");
+    assert_eq!(mask_key("short"), "*****");
+}
+
+#[test]
+fn test_empty_key_list_is_rejected() {
+    let contents = r#"
+[qwen]
+model_name = "qwen2.5-instruct"
+provider = "xinference"
+url = "http://127.0.0.1:9997/v1/chat/completions"
+api_key = []
+"#;
+    let error = TestConfig::new(contents).unwrap().load().unwrap_err();
+    assert_eq!(error.to_string(), "qwen.api_key is an empty list");
+
+    let diagnostics = check(contents);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "qwen.api_key is an empty list");
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use anyhow::Result;
+use axum::routing::post;
+use axum::Router;
+use common::{serve, spawn_app, TestConfig};
+use lumos::limits::Limits;
+use lumos::structs::config::ClientKey;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// A completion followed by its usage, like OpenAI with `include_usage`
+async fn completions() -> &'static str {
+    concat!(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Beijing\"}}]}\n\n",
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":30,\"total_tokens\":42}}\n\n",
+        "data: [DONE]\n\n"
+    )
+}
+
+#[tokio::test]
+async fn test_rate_limit_and_usage() -> Result<()> {
+    let upstream = serve(Router::new().route("/chat/completions", post(completions))).await?;
+
+    let config = TestConfig::empty()?;
+    let usage_file = config.file("usage.json");
+    config.write(&format!(
+        r#"
+[auth]
+usage_file = "{usage_file}"
+
+[[auth.clients]]
+name = "editor"
+key = "sk-editor"
+models = ["*"]
+requests_per_minute = 1
+
+[[auth.clients]]
+name = "ops"
+key = "sk-ops"
+models = ["*"]
+admin = true
+
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "{upstream}/chat/completions"
+api_key = ""
+"#
+    ))?;
+    let addr = spawn_app(config.state("glm-4-plus")).await?;
+    let client = Client::new();
+    let chat = json!({
+        "model": "glm-4-plus",
+        "messages": [{"role": "user", "content": "Where is the capital of China?"}]
+    });
+
+    let response = client
+        .post(format!("{}/api/chat", addr))
+        .bearer_auth("sk-editor")
+        .json(&chat)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await?;
+    assert!(body.contains("\"eval_count\":30"));
+
+    let response = client
+        .post(format!("{}/api/chat", addr))
+        .bearer_auth("sk-editor")
+        .json(&chat)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(RETRY_AFTER));
+
+    let usage: Value = client
+        .get(format!("{}/admin/usage", addr))
+        .bearer_auth("sk-ops")
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(usage["clients"]["editor"]["total_tokens"], 42);
+    assert_eq!(usage["clients"]["editor"]["total_requests"], 1);
+
+    // saved in the background
+    let mut saved = Value::Null;
+    for _ in 0..40 {
+        if let Ok(contents) = std::fs::read_to_string(&usage_file) {
+            saved = serde_json::from_str(&contents)?;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(saved["editor"]["day_tokens"], 42);
+
+    Ok(())
+}
+
+#[test]
+fn test_no_requests_per_minute() -> Result<()> {
+    let client: ClientKey = toml::from_str(
+        r#"
+name = "blocked"
+key = "sk-blocked"
+models = ["*"]
+requests_per_minute = 0
+"#,
+    )?;
+    let config = TestConfig::empty()?;
+    let usage_file = config.file("usage.json");
+    let limits = Limits::default();
+
+    // refused every time, the limits stay usable
+    for _ in 0..2 {
+        let limited = limits.acquire(&client, &usage_file).err().unwrap();
+        assert_eq!(limited.message, "0 requests per minute exceeded");
+        assert_eq!(limited.retry_after, Duration::from_secs(60));
+    }
+    assert_eq!(limits.consumption()["blocked"].total_requests, 0);
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use anyhow::Result;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::Router;
+use common::{body, message, serve, TestConfig};
+use lumos::ollama::{dispatch, Dispatch};
+use lumos::structs::ollama::ChatType;
+
+async fn unavailable() -> impl IntoResponse {
+    (StatusCode::SERVICE_UNAVAILABLE, "overloaded")
+}
+
+async fn completions() -> &'static str {
+    concat!(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Beijing\"}}]}\n\n",
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":30,\"total_tokens\":42}}\n\n",
+        "data: [DONE]\n\n"
+    )
+}
+
+#[tokio::test]
+async fn test_dispatch_metrics() -> Result<()> {
+    let upstream = serve(
+        Router::new()
+            .route("/down/chat/completions", post(unavailable))
+            .route("/up/chat/completions", post(completions)),
+    )
+    .await?;
+
+    let test_config = TestConfig::new(&format!(
+        r#"
+[deepseek-chat]
+model_name = "deepseek-chat"
+provider = "deepseek"
+url = "{upstream}/down/chat/completions"
+api_key = ""
+fallbacks = ["glm-4-plus"]
+
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "{upstream}/up/chat/completions"
+api_key = ""
+
+[deepseek-down]
+model_name = "deepseek-chat"
+provider = "deepseek"
+url = "{upstream}/down/chat/completions"
+api_key = ""
+"#
+    ))?;
+    let config = test_config.load()?;
+    let state = test_config.state("");
+
+    let messages = vec![message("user", "Where is the capital of China?")];
+    let ctx = Dispatch::new(state.clone(), &config, "deepseek-chat".to_string(), None);
+    body(dispatch(ctx, messages.clone(), ChatType::Chat, None).await?).await;
+
+    // a failed request is counted and timed too
+    let ctx = Dispatch::new(state.clone(), &config, "deepseek-down".to_string(), None);
+    assert!(dispatch(ctx, messages, ChatType::Chat, None).await.is_err());
+
+    let metrics = state.metrics.render();
+    let expected = [
+        r#"lumos_requests_total{model="glm-4-plus",provider="zhipu"} 1"#,
+        r#"lumos_requests_total{model="deepseek-down",provider="deepseek"} 1"#,
+        r#"lumos_errors_total{model="deepseek-chat",provider="deepseek",kind="status",status="503"} 1"#,
+        r#"lumos_tokens_total{model="glm-4-plus",provider="zhipu",direction="input"} 12"#,
+        r#"lumos_tokens_total{model="glm-4-plus",provider="zhipu",direction="output"} 30"#,
+        r#"lumos_streams_in_flight{model="glm-4-plus",provider="zhipu"} 0"#,
+        r#"lumos_time_to_first_token_seconds_count{model="glm-4-plus",provider="zhipu"} 1"#,
+        r#"lumos_request_duration_seconds_bucket{model="glm-4-plus",provider="zhipu",le="+Inf"} 1"#,
+        r#"lumos_request_duration_seconds_count{model="deepseek-down",provider="deepseek"} 1"#,
+        "# TYPE lumos_request_duration_seconds histogram",
+    ];
+    for line in expected {
+        assert!(metrics.contains(line), "missing {} in\n{}", line, metrics);
+    }
+    // counted once per request, not per attempt
+    assert!(!metrics.contains(r#"lumos_requests_total{model="deepseek-chat""#));
+
+    Ok(())
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use anyhow::Result;
+use common::TestConfig;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+/// Serve the model, its config lives as long as the returned `TestConfig`
+async fn spawn_app(model_name: &str, config: &str) -> Result<(TestConfig, String)> {
+    let config = TestConfig::new(config)?;
+    let addr = common::spawn_app(config.state(model_name)).await?;
+    Ok((config, addr))
+}
+
+/// The streamed chunks, and whether the stream ended cleanly
+async fn stream(addr: &str, endpoint: &str, body: Value) -> Result<(Vec<Value>, bool)> {
+    let mut response = Client::new()
+        .post(format!("{}/api/{}", addr, endpoint))
+        .json(&body)
+        .send()
+        .await?;
+    let mut text = String::new();
+    let mut complete = true;
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => text.push_str(&String::from_utf8_lossy(&chunk)),
+            Ok(None) => break,
+            Err(_) => {
+                complete = false;
+                break;
+            }
+        }
+    }
+    let chunks = text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    Ok((chunks, complete))
+}
+
+fn text(chunks: &[Value], key: &str) -> String {
+    chunks
+        .iter()
+        .filter_map(|chunk| {
+            chunk[key]
+                .as_str()
+                .or_else(|| chunk["message"][key].as_str())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_mock_echo() -> Result<()> {
+    let (_config, addr) = spawn_app(
+        "mock-echo",
+        r#"
+[mock-echo]
+model_name = "mock"
+provider = "mock"
+api_key = ""
+
+[mock-echo.mock]
+mode = "echo"
+latency_ms = 50
+"#,
+    )
+    .await?;
+
+    let started = Instant::now();
+    let (chunks, complete) = stream(
+        &addr,
+        "chat",
+        json!({"model": "mock:echo", "messages": [{"role": "user", "content": "hello from the frontend"}]}),
+    )
+    .await?;
+    assert!(started.elapsed() >= Duration::from_millis(50));
+    assert!(complete);
+    assert_eq!(text(&chunks, "content"), "hello from the frontend");
+    let done = chunks.last().unwrap();
+    assert_eq!(done["done"], true);
+    assert_eq!(done["prompt_eval_count"], 4);
+    assert_eq!(done["eval_count"], 4);
+
+    let (chunks, _) = stream(
+        &addr,
+        "generate",
+        json!({"model": "mock:echo", "prompt": "echo me"}),
+    )
+    .await?;
+    assert_eq!(text(&chunks, "response"), "echo me");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_fixed() -> Result<()> {
+    let (_config, addr) = spawn_app(
+        "mock-fixed",
+        r#"
+[mock-fixed]
+model_name = "mock"
+provider = "mock"
+api_key = ""
+mock = { mode = "fixed", text = "The capital of China is Beijing." }
+"#,
+    )
+    .await?;
+
+    let (chunks, complete) = stream(
+        &addr,
+        "chat",
+        json!({"model": "mock:fixed", "messages": [{"role": "user", "content": "hi"}]}),
+    )
+    .await?;
+    assert!(complete);
+    assert_eq!(chunks.len(), 7);
+    assert_eq!(text(&chunks, "content"), "The capital of China is Beijing.");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_lorem_and_errors() -> Result<()> {
+    let (_config, addr) = spawn_app(
+        "mock-lorem",
+        r#"
+[mock-lorem]
+model_name = "mock"
+provider = "mock"
+api_key = ""
+
+[mock-lorem.mock]
+mode = "lorem"
+tokens = 6
+tokens_per_sec = 100
+error_at_chunk = 4
+"#,
+    )
+    .await?;
+
+    let started = Instant::now();
+    let (chunks, complete) = stream(
+        &addr,
+        "chat",
+        json!({"model": "mock:lorem", "messages": [{"role": "user", "content": "hi"}]}),
+    )
+    .await?;
+    assert!(started.elapsed() >= Duration::from_millis(40));
+    assert!(!complete);
+    assert!("lorem ipsum dolor sit".starts_with(&text(&chunks, "content")));
+    assert!(chunks.iter().all(|chunk| chunk["done"] == false));
+
+    let (_config, addr) = spawn_app(
+        "mock-down",
+        r#"
+[mock-down]
+model_name = "mock"
+provider = "mock"
+api_key = ""
+mock = { fail_status = 503 }
+"#,
+    )
+    .await?;
+    let response = Client::new()
+        .post(format!("{}/api/chat", addr))
+        .json(&json!({"model": "mock:down", "messages": []}))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(response.text().await?.contains("503"));
+
+    Ok(())
+}
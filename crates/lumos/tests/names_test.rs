@@ -0,0 +1,160 @@
+mod common;
+
+use anyhow::Result;
+use common::{spawn_app, TestConfig};
+use lumos::check::check;
+use lumos::names::{normalize, ollama_name};
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+
+const CONFIG: &str = r#"
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "mock"
+api_key = ""
+aliases = ["glm4", "zhipu/glm-4-plus"]
+system_prompt = "You are helpful."
+options = { temperature = 0.2, stop = ["<|end|>"] }
+
+["qwen2.5:72b"]
+model_name = "qwen2.5-72b-instruct"
+provider = "mock"
+api_key = ""
+
+[deepseek-chat]
+model_name = "deepseek-chat"
+provider = "mock"
+api_key = ""
+"#;
+
+#[test]
+fn test_normalize() {
+    assert_eq!(normalize("GLM-4-Plus:latest"), "glm-4-plus");
+    assert_eq!(normalize("glm-4-plus:v2"), "glm-4-plus:v2");
+    assert_eq!(ollama_name("glm-4-plus"), "glm:4-plus");
+    assert_eq!(ollama_name("qwen2.5:72b"), "qwen2.5:72b");
+    assert_eq!(ollama_name("mock"), "mock");
+}
+
+#[test]
+fn test_resolve() -> Result<()> {
+    let config = TestConfig::new(CONFIG)?.load()?;
+    for (requested, alias) in [
+        ("glm-4-plus", Some("glm-4-plus")),
+        ("glm:4-plus", Some("glm-4-plus")),
+        ("glm:4-plus:latest", Some("glm-4-plus")),
+        ("GLM-4-Plus:latest", Some("glm-4-plus")),
+        ("glm4:latest", Some("glm-4-plus")),
+        ("Zhipu/GLM-4-Plus", Some("glm-4-plus")),
+        ("qwen2.5:72b", Some("qwen2.5:72b")),
+        ("QWEN2.5:72B", Some("qwen2.5:72b")),
+        ("qwen2.5", None),
+        ("deepseek:chat", Some("deepseek-chat")),
+        ("deepseek-chat:latest", Some("deepseek-chat")),
+        ("deepseek-chat:v3", None),
+    ] {
+        assert_eq!(config.resolve(requested), alias, "{}", requested);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_names_told_apart() {
+    let diagnostics = check(
+        r#"[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "mock"
+api_key = ""
+aliases = ["GLM4", "glm-4-plus:latest"]
+
+["glm:4-plus"]
+model_name = "glm-4-plus"
+provider = "mock"
+api_key = ""
+
+[glm-4-air]
+model_name = "glm-4-air"
+provider = "mock"
+api_key = ""
+aliases = ["glm4"]
+"#,
+    );
+    let messages = diagnostics
+        .iter()
+        .map(|diagnostic| (diagnostic.line, diagnostic.message.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            (
+                7,
+                "name glm:4-plus of model glm:4-plus is the same as glm:4-plus of model glm-4-plus on line 1 once normalised"
+            ),
+            (
+                16,
+                "name glm4 of model glm-4-air is the same as GLM4 of model glm-4-plus on line 5 once normalised"
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_every_route_resolves_names() -> Result<()> {
+    let config = TestConfig::new(CONFIG)?;
+    let addr = spawn_app(config.state("glm-4-plus")).await?;
+    let client = Client::new();
+
+    let tags: Value = client
+        .get(format!("{}/api/tags", addr))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(tags["models"][0]["name"], "glm:4-plus");
+
+    for model in ["glm:4-plus", "glm4:latest", "GLM-4-Plus"] {
+        let response = client
+            .post(format!("{}/api/chat", addr))
+            .json(&json!({ "model": model, "messages": [{ "role": "user", "content": "hi" }] }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK, "{}", model);
+        let first: Value = serde_json::from_str(response.text().await?.lines().next().unwrap())?;
+        assert_eq!(first["model"], "glm:4-plus");
+
+        let response = client
+            .post(format!("{}/api/generate", addr))
+            .json(&json!({ "model": model, "prompt": "hi" }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK, "{}", model);
+    }
+
+    let show: Value = client
+        .post(format!("{}/api/show", addr))
+        .json(&json!({ "name": "glm4" }))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(show["system"], "You are helpful.");
+    assert_eq!(show["parameters"], "temperature 0.2\nstop \"<|end|>\"");
+    assert!(show["modelfile"]
+        .as_str()
+        .unwrap()
+        .starts_with("# Modelfile generated by lumos\nFROM glm-4-plus\n"));
+    assert_eq!(show["model_info"]["general.architecture"], "mock");
+    assert_eq!(show["capabilities"], json!(["completion"]));
+
+    // configured, but not the served model
+    let response = client
+        .post(format!("{}/api/show", addr))
+        .json(&json!({ "model": "deepseek:chat" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let error: Value = response.json().await?;
+    assert_eq!(error["error"], "model 'deepseek:chat' not found");
+
+    Ok(())
+}
@@ -10,14 +10,17 @@ use std::sync::Arc;
 use lumos::structs::app::AppState;
 use lumos::structs::ollama::{ChatRequest, GenerateRequest, Message};
 
-async fn spawn_app(app_state: Arc<AppState>) {
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:11434")
-        .await
-        .unwrap();
+/// Upstream responses are replayed from the cassettes, no keys or network needed
+const CONFIG_PATH: &str = "tests/cassettes/replay.toml";
+
+async fn spawn_app(app_state: Arc<AppState>) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
     let app = lumos::app::create_app(app_state.clone()).await;
     tokio::spawn(async move {
         axum::serve(listener, app).await.unwrap();
     });
+    addr
 }
 
 async fn process_request<T: serde::Serialize>(
@@ -81,15 +84,13 @@ async fn process_request<T: serde::Serialize>(
 #[tokio::test]
 async fn test_generate() -> Result<()> {
     let model_name = "glm-4-plus";
-    let app_state = Arc::new(AppState {
-        model_name: model_name.to_string(),
-        config_path: "keys.toml".to_string(),
-    });
-
-    spawn_app(app_state).await;
+    let app_state = Arc::new(AppState::new(
+        model_name.to_string(),
+        CONFIG_PATH.to_string(),
+    ));
 
+    let addr = spawn_app(app_state).await;
     let client = Client::new();
-    let addr = "localhost:11434";
 
     let req = GenerateRequest {
         model: model_name.replacen("-", ":", 1).to_string(),
@@ -98,7 +99,7 @@ async fn test_generate() -> Result<()> {
         ..Default::default()
     };
 
-    let response_text = process_request(&client, addr, "generate", req, "response").await?;
+    let response_text = process_request(&client, &addr, "generate", req, "response").await?;
     assert!(response_text.to_lowercase().contains("beijing"));
 
     Ok(())
@@ -107,15 +108,13 @@ async fn test_generate() -> Result<()> {
 #[tokio::test]
 async fn test_chat() -> Result<()> {
     let model_name = "glm-4-plus";
-    let app_state = Arc::new(AppState {
-        model_name: model_name.to_string(),
-        config_path: "keys.toml".to_string(),
-    });
-
-    spawn_app(app_state).await;
+    let app_state = Arc::new(AppState::new(
+        model_name.to_string(),
+        CONFIG_PATH.to_string(),
+    ));
 
+    let addr = spawn_app(app_state).await;
     let client = Client::new();
-    let addr = "localhost:11434";
 
     let req = ChatRequest {
         model: model_name.replacen(":", "-", 1).to_string(),
@@ -127,7 +126,7 @@ async fn test_chat() -> Result<()> {
         ..Default::default()
     };
 
-    let response_text = process_request(&client, addr, "chat", req, "content").await?;
+    let response_text = process_request(&client, &addr, "chat", req, "content").await?;
     assert!(response_text.to_lowercase().contains("beijing"));
 
     Ok(())
@@ -0,0 +1,112 @@
+mod common;
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use common::{body, message, serve, TestConfig};
+use lumos::ollama::{dispatch, Dispatch};
+use lumos::structs::ollama::{ChatOptions, ChatType};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+type Received = Arc<Mutex<Vec<Value>>>;
+
+async fn completions(State(received): State<Received>, Json(body): Json<Value>) -> &'static str {
+    received.lock().unwrap().push(body);
+    "data: {\"choices\":[{\"delta\":{\"content\":\"ok\"}}]}\n\ndata: [DONE]\n\n"
+}
+
+#[tokio::test]
+async fn test_model_options_and_system_prompt() -> Result<()> {
+    let received = Received::default();
+    let upstream = serve(
+        Router::new()
+            .route("/chat/completions", post(completions))
+            .with_state(received.clone()),
+    )
+    .await?;
+
+    let test_config = TestConfig::new(&format!(
+        r#"
+[providers.zhipu]
+url = "{upstream}/chat/completions"
+api_key = ""
+
+[models.glm-coder]
+provider = "zhipu"
+model_name = "glm-4-plus"
+system_prompt = "You are a senior Rust developer."
+options = {{ temperature = 0.1, max_tokens = 512, stop = ["```"] }}
+
+[qwen]
+model_name = "qwen2.5-instruct"
+provider = "xinference"
+url = "{upstream}/chat/completions"
+api_key = ""
+"#
+    ))?;
+    let config = test_config.load()?;
+    let state = test_config.state("");
+
+    let requests = [
+        (
+            vec![message("user", "Write a parser")],
+            ChatOptions::default(),
+        ),
+        (
+            vec![
+                message("system", "Answer briefly."),
+                message("user", "Write a parser"),
+            ],
+            ChatOptions {
+                temperature: Some(0.9),
+                num_predict: Some(64),
+                stop: Some(vec!["END".to_string()]),
+                ..Default::default()
+            },
+        ),
+    ];
+    for (messages, options) in requests {
+        let ctx = Dispatch::new(state.clone(), &config, "glm-coder".to_string(), None)
+            .with_options(options);
+        body(dispatch(ctx, messages, ChatType::Chat, None).await?).await;
+    }
+
+    let ctx = Dispatch::new(state.clone(), &config, "qwen".to_string(), None);
+    let messages = vec![message("user", "Write a parser")];
+    body(dispatch(ctx, messages, ChatType::Chat, None).await?).await;
+
+    let received = received.lock().unwrap();
+    // only the providers that support it are asked for the usage
+    assert_eq!(
+        received[0]["stream_options"],
+        json!({ "include_usage": true })
+    );
+    assert!(received[2].get("stream_options").is_none());
+
+    // the defaults of the model
+    assert_eq!(received[0]["model"], "glm-4-plus");
+    assert_eq!(received[0]["temperature"], 0.1);
+    assert_eq!(received[0]["max_tokens"], 512);
+    assert_eq!(received[0]["stop"], json!(["```"]));
+    assert_eq!(
+        received[0]["messages"],
+        json!([
+            { "role": "system", "content": "You are a senior Rust developer." },
+            { "role": "user", "content": "Write a parser" }
+        ])
+    );
+
+    // the options of the request win, the system prompts are merged
+    assert_eq!(received[1]["temperature"], 0.9);
+    assert_eq!(received[1]["max_tokens"], 64);
+    assert_eq!(received[1]["stop"], json!(["END"]));
+    assert_eq!(
+        received[1]["messages"][0]["content"],
+        "You are a senior Rust developer.\n\nAnswer briefly."
+    );
+    assert_eq!(received[1]["messages"].as_array().unwrap().len(), 2);
+
+    Ok(())
+}
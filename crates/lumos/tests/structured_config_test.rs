@@ -0,0 +1,174 @@
+mod common;
+
+use anyhow::Result;
+use common::TestConfig;
+use lumos::check::{check, Severity};
+use lumos::structs::config::{ApiKey, ProviderName};
+
+const STRUCTURED: &str = r#"
+[server]
+host = "0.0.0.0"
+port = 8080
+cors = { allow_origins = ["https://chat.example.com"] }
+
+[[server.auth.clients]]
+name = "web"
+key = "sk-client"
+models = ["*"]
+
+[providers.zhipu]
+url = "https://open.bigmodel.cn/api/paas/v4/chat/completions"
+api_key = ["sk-one", "sk-two"]
+timeouts = { connect_secs = 5, total_secs = 300 }
+
+[providers.zhipu.retry]
+max_attempts = 2
+base_delay_ms = 100
+
+[providers.deepseek-beta]
+kind = "deepseek"
+url = "https://api.deepseek.com/beta/chat/completions"
+api_key = "sk-three"
+
+[models.glm-4-plus]
+provider = "zhipu"
+fallbacks = ["deepseek-chat"]
+
+[models.glm-4-long]
+provider = "zhipu"
+retry = { max_attempts = 5 }
+
+[models.deepseek-chat]
+provider = "deepseek-beta"
+model_name = "deepseek-chat"
+
+[models.mock]
+provider = "mock"
+api_key = ""
+
+[qwen25-72b-instuct]
+model_name = "qwen2.5-72b-instruct"
+provider = "xinference"
+url = "http://127.0.0.1:9997/v1/chat/completions"
+api_key = ""
+"#;
+
+#[test]
+fn test_structured_config() -> Result<()> {
+    let config = TestConfig::new(STRUCTURED)?.load()?;
+
+    assert_eq!(config.server.host.as_deref(), Some("0.0.0.0"));
+    assert_eq!(config.server.port, Some(8080));
+    assert_eq!(
+        config.server.cors.allow_origins,
+        ["https://chat.example.com"]
+    );
+    assert_eq!(config.auth.as_ref().unwrap().clients[0].name, "web");
+    assert_eq!(config.models().len(), 5);
+
+    // everything but the provider comes from the provider section
+    let plus = config.get_model("glm-4-plus").unwrap();
+    assert_eq!(plus.model_name, "glm-4-plus");
+    assert_eq!(plus.provider, ProviderName::Zhipu);
+    assert_eq!(
+        plus.url,
+        "https://open.bigmodel.cn/api/paas/v4/chat/completions"
+    );
+    assert!(matches!(&plus.api_key, ApiKey::Many(keys) if keys.len() == 2));
+    assert_eq!(plus.timeouts.connect_secs, Some(5));
+    assert_eq!(plus.timeouts.total_secs, Some(300));
+    assert_eq!(plus.fallbacks, ["deepseek-chat"]);
+
+    // tables are merged field by field
+    let retry = config
+        .get_model("glm-4-long")
+        .unwrap()
+        .retry
+        .clone()
+        .unwrap();
+    assert_eq!(retry.max_attempts, 5);
+    assert_eq!(retry.base_delay_ms, 100);
+
+    let deepseek = config.get_model("deepseek-chat").unwrap();
+    assert_eq!(deepseek.provider, ProviderName::DeepSeek);
+    assert_eq!(
+        deepseek.url,
+        "https://api.deepseek.com/beta/chat/completions"
+    );
+
+    assert_eq!(
+        config.get_model("mock").unwrap().provider,
+        ProviderName::Mock
+    );
+    assert_eq!(
+        config.get_model("qwen25-72b-instuct").unwrap().provider,
+        ProviderName::Xinference
+    );
+
+    assert!(check(STRUCTURED).is_empty(), "{:?}", check(STRUCTURED));
+    Ok(())
+}
+
+#[test]
+fn test_structured_config_errors() -> Result<()> {
+    let unknown =
+        TestConfig::new("[models.glm-4-plus]\nprovider = \"zhipu-cn\"\napi_key = \"\"\n")?;
+    let error = unknown.load().err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        "models.glm-4-plus references unknown provider zhipu-cn"
+    );
+
+    let twice = TestConfig::new(
+        r#"
+[models.mock]
+provider = "mock"
+api_key = ""
+
+[mock]
+model_name = "mock"
+provider = "mock"
+api_key = ""
+"#,
+    )?;
+    let error = twice.load().err().unwrap();
+    assert!(error.to_string().contains("declared both"));
+
+    let diagnostics = check(
+        r#"[providers.zhipu]
+url = "https://open.bigmodel.cn/api/paas/v4/chat/completions"
+api_kye = "sk-one"
+
+[providers.openai]
+url = "https://api.openai.com/v1/chat/completions"
+
+[models.glm-4-plus]
+provider = "zhipu"
+fallbacks = ["glm-4-air"]
+
+[models.gpt-4o]
+provider = "openai"
+"#,
+    );
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .map(|diagnostic| (diagnostic.line, diagnostic.message.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(errors.len(), 4, "{:?}", errors);
+    assert_eq!(errors[0].0, 3);
+    assert!(errors[0]
+        .1
+        .contains("unknown field api_kye in providers.zhipu"));
+    assert_eq!(errors[1].0, 5);
+    assert!(errors[1].1.contains("providers.openai has no valid kind"));
+    // the model has no key as the provider field is misspelled
+    assert_eq!(errors[2].0, 8);
+    assert!(errors[2].1.contains("missing field `api_key`"));
+    assert_eq!(
+        errors[3],
+        (10, "glm-4-plus falls back to unknown model glm-4-air")
+    );
+
+    Ok(())
+}
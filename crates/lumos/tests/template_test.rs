@@ -0,0 +1,208 @@
+mod common;
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use common::{serve, spawn_app, TestConfig};
+use lumos::ollama::render_template;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+type Received = Arc<Mutex<Vec<Value>>>;
+
+async fn chat_completions(
+    State(received): State<Received>,
+    Json(body): Json<Value>,
+) -> &'static str {
+    received.lock().unwrap().push(body);
+    "data: {\"choices\":[{\"delta\":{\"content\":\"Beijing\"}}]}\n\ndata: [DONE]\n\n"
+}
+
+async fn completions(State(received): State<Received>, Json(body): Json<Value>) -> &'static str {
+    received.lock().unwrap().push(body);
+    "data: {\"choices\":[{\"index\":0,\"text\":\"Beijing\"}]}\n\ndata: [DONE]\n\n"
+}
+
+/// A fake upstream recording the chat and the completion requests
+async fn upstream(received: &Received) -> Result<String> {
+    serve(
+        Router::new()
+            .route("/chat/completions", post(chat_completions))
+            .route("/completions", post(completions))
+            .with_state(received.clone()),
+    )
+    .await
+}
+
+/// Post to `/api/generate`, returns the status and the lines of the response
+async fn generate(addr: &str, request: Value) -> Result<(StatusCode, Vec<Value>)> {
+    let response = Client::new()
+        .post(format!("{}/api/generate", addr))
+        .json(&request)
+        .send()
+        .await?;
+    let status = response.status();
+    let lines = response
+        .text()
+        .await?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<Value>, _>>()?;
+    Ok((status, lines))
+}
+
+#[test]
+fn test_render_chatml() -> Result<()> {
+    let template = "{{ if .System }}<|im_start|>system\n{{ .System }}<|im_end|>\n{{ end }}\
+        <|im_start|>user\n{{ .Prompt }}<|im_end|>\n<|im_start|>assistant\n{{ .Response }}<|im_end|>";
+
+    let rendered = render_template(template, Some("Be brief."), "Hi")?;
+    assert_eq!(
+        rendered,
+        "<|im_start|>system\nBe brief.<|im_end|>\n<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\n"
+    );
+
+    let rendered = render_template(template, None, "Hi")?;
+    assert!(rendered.starts_with("<|im_start|>user\nHi"));
+
+    Ok(())
+}
+
+#[test]
+fn test_render_else_and_trim() -> Result<()> {
+    let template = "{{- if .System }}[{{ .System }}]{{ else if .Prompt }}no system{{ else }}empty{{ end -}}\n  {{ .Prompt }}";
+
+    assert_eq!(render_template(template, Some("sys"), "p")?, "[sys]p");
+    assert_eq!(render_template(template, None, "p")?, "no systemp");
+    assert_eq!(render_template(template, None, "")?, "empty");
+
+    Ok(())
+}
+
+#[test]
+fn test_render_errors() {
+    assert!(render_template("{{ .Messages }}", None, "").is_err());
+    assert!(render_template("{{ if .System }}unclosed", None, "").is_err());
+    assert!(render_template("{{ .Prompt", None, "").is_err());
+}
+
+#[tokio::test]
+async fn test_raw_and_template_need_completion_endpoint() -> Result<()> {
+    let config = TestConfig::new(
+        r#"
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "http://127.0.0.1:9/chat/completions"
+api_key = ""
+"#,
+    )?;
+    let addr = spawn_app(config.state("glm-4-plus")).await?;
+
+    let requests = [
+        (
+            json!({ "model": "glm-4-plus", "prompt": "Hi", "template": "<|user|>{{ .Prompt }}<|assistant|>" }),
+            "Model glm-4-plus needs a completion_url for template",
+        ),
+        (
+            json!({ "model": "glm-4-plus", "prompt": "<|user|>Hi<|assistant|>", "raw": true }),
+            "Model glm-4-plus needs a completion_url for raw",
+        ),
+    ];
+    for (request, error) in requests {
+        let (status, lines) = generate(&addr, request).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(lines, [json!({ "error": error })]);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_raw_prompt_is_sent_untouched() -> Result<()> {
+    let received = Received::default();
+    let upstream = upstream(&received).await?;
+    let config = TestConfig::new(&format!(
+        r#"
+[qwen]
+model_name = "qwen2.5-instruct"
+provider = "xinference"
+url = "{upstream}/chat/completions"
+completion_url = "{upstream}/completions"
+system_prompt = "Be helpful."
+api_key = ""
+"#
+    ))?;
+    let addr = spawn_app(config.state("qwen")).await?;
+
+    let prompt =
+        "<|im_start|>user\nWhere is the capital of China?<|im_end|>\n<|im_start|>assistant\n";
+    let (status, lines) = generate(
+        &addr,
+        json!({ "model": "qwen", "prompt": prompt, "system": "Be brief.", "raw": true }),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(lines[0]["response"], "Beijing");
+
+    // neither the system of the request nor the one of the model is added
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0]["prompt"], prompt);
+    assert!(received[0].get("messages").is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_system_replaces_the_one_of_the_context() -> Result<()> {
+    let received = Received::default();
+    let upstream = upstream(&received).await?;
+    let config = TestConfig::new(&format!(
+        r#"
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "{upstream}/chat/completions"
+api_key = ""
+"#
+    ))?;
+    let addr = spawn_app(config.state("glm-4-plus")).await?;
+
+    let (_, lines) = generate(
+        &addr,
+        json!({ "model": "glm-4-plus", "prompt": "Where is the capital of China?", "system": "Be verbose." }),
+    )
+    .await?;
+    let context = lines.last().unwrap()["context"].clone();
+    assert!(context.is_array());
+
+    let (status, _) = generate(
+        &addr,
+        json!({ "model": "glm-4-plus", "prompt": "And of Japan?", "system": "Be brief.", "context": context }),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let received = received.lock().unwrap();
+    assert_eq!(
+        received[0]["messages"],
+        json!([
+            { "role": "system", "content": "Be verbose." },
+            { "role": "user", "content": "Where is the capital of China?" },
+        ])
+    );
+    assert_eq!(
+        received[1]["messages"],
+        json!([
+            { "role": "system", "content": "Be brief." },
+            { "role": "user", "content": "Where is the capital of China?" },
+            { "role": "assistant", "content": "Beijing" },
+            { "role": "user", "content": "And of Japan?" },
+        ])
+    );
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use anyhow::Result;
+use common::TestConfig;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use lumos::structs::app::AppState;
+
+const CONFIG: &str = r#"
+[slow]
+model_name = "slow"
+provider = "mock"
+api_key = ""
+mock = { mode = "fixed", text = "slow answer", latency_ms = 1500 }
+timeouts = { first_byte_secs = 1 }
+
+[slow-fallback]
+model_name = "slow"
+provider = "mock"
+api_key = ""
+mock = { mode = "fixed", text = "slow answer", latency_ms = 1500 }
+timeouts = { first_byte_secs = 1 }
+fallbacks = ["fast"]
+
+[late]
+model_name = "late"
+provider = "mock"
+api_key = ""
+mock = { mode = "fixed", text = "late answer", latency_ms = 1500 }
+timeouts = { total_secs = 1 }
+
+[late-fallback]
+model_name = "slow"
+provider = "mock"
+api_key = ""
+mock = { mode = "fixed", text = "slow answer", latency_ms = 1500 }
+timeouts = { first_byte_secs = 1, total_secs = 2 }
+fallbacks = ["late-too"]
+
+[late-too]
+model_name = "late"
+provider = "mock"
+api_key = ""
+mock = { mode = "fixed", text = "late answer", latency_ms = 1500 }
+timeouts = { total_secs = 2 }
+
+[stalling]
+model_name = "stalling"
+provider = "mock"
+api_key = ""
+mock = { mode = "fixed", text = "one two three", tokens_per_sec = 0.8 }
+timeouts = { idle_secs = 1 }
+
+[fast]
+model_name = "fast"
+provider = "mock"
+api_key = ""
+mock = { mode = "fixed", text = "fast answer" }
+"#;
+
+async fn spawn_app(config: &TestConfig, model_name: &str) -> Result<(Arc<AppState>, String)> {
+    let state = config.state(model_name);
+    let addr = common::spawn_app(state.clone()).await?;
+    Ok((state, addr))
+}
+
+async fn chat(addr: &str, model: &str) -> Result<(StatusCode, String)> {
+    let response = Client::new()
+        .post(format!("{}/api/chat", addr))
+        .json(&json!({ "model": model, "messages": [{ "role": "user", "content": "hi" }] }))
+        .send()
+        .await?;
+    Ok((response.status(), response.text().await?))
+}
+
+#[tokio::test]
+async fn test_timeouts_before_streaming() -> Result<()> {
+    let config = TestConfig::new(CONFIG)?;
+    let (state, addr) = spawn_app(&config, "slow").await?;
+    let (status, body) = chat(&addr, "slow").await?;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    let error: Value = serde_json::from_str(&body)?;
+    assert_eq!(
+        error["error"],
+        "API请求超时: no response within 1s (first_byte_secs)"
+    );
+    assert!(state.metrics.render().contains(
+        "lumos_errors_total{model=\"slow\",provider=\"mock\",kind=\"first_byte_timeout\",status=\"\"} 1"
+    ));
+
+    let (_, addr) = spawn_app(&config, "late").await?;
+    let (status, body) = chat(&addr, "late").await?;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    let error: Value = serde_json::from_str(&body)?;
+    assert_eq!(
+        error["error"],
+        "API请求超时: not finished within 1s (total_secs)"
+    );
+
+    let (_, addr) = spawn_app(&config, "slow-fallback").await?;
+    let (status, body) = chat(&addr, "slow-fallback").await?;
+    assert_eq!(status, StatusCode::OK);
+    let answer = body
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|line| line["message"]["content"].as_str().map(String::from))
+        .collect::<String>();
+    assert_eq!(answer, "fast answer");
+
+    // the fallback gets what is left of the total, not a new one
+    let (_, addr) = spawn_app(&config, "late-fallback").await?;
+    let (status, body) = chat(&addr, "late-fallback").await?;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    let error: Value = serde_json::from_str(&body)?;
+    assert_eq!(
+        error["error"],
+        "API请求超时: not finished within 2s (total_secs)"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_idle_timeout_while_streaming() -> Result<()> {
+    let config = TestConfig::new(CONFIG)?;
+    let (_, addr) = spawn_app(&config, "stalling").await?;
+    let (status, body) = chat(&addr, "stalling").await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let lines = body
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<Value>, _>>()?;
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["message"]["content"], "one");
+    assert_eq!(
+        lines[1],
+        json!({ "error": "API请求超时: stream idle for 1s (idle_secs)" })
+    );
+
+    Ok(())
+}
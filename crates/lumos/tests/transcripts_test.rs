@@ -0,0 +1,88 @@
+mod common;
+
+use anyhow::Result;
+use axum::routing::post;
+use axum::Router;
+use common::{body, message, serve, TestConfig};
+use lumos::ollama::{dispatch, Dispatch};
+use lumos::structs::ollama::{ChatRequest, ChatType};
+use serde_json::Value;
+
+async fn completions() -> &'static str {
+    concat!(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Bei\"}}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{\"content\":\"jing\"}}]}\n\n",
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":30,\"total_tokens\":42}}\n\n",
+        "data: [DONE]\n\n"
+    )
+}
+
+#[tokio::test]
+async fn test_transcripts() -> Result<()> {
+    let upstream = serve(Router::new().route("/chat/completions", post(completions))).await?;
+
+    let test_config = TestConfig::empty()?;
+    let dir = test_config.dir().join("transcripts");
+    test_config.write(&format!(
+        r#"
+[transcripts]
+dir = "{dir}"
+max_file_bytes = 100
+max_files = 1
+
+[glm-4-plus]
+model_name = "glm-4-plus"
+provider = "zhipu"
+url = "{upstream}/chat/completions"
+api_key = "sk-transcript-secret"
+transcript = true
+
+[deepseek-chat]
+model_name = "deepseek-chat"
+provider = "deepseek"
+url = "{upstream}/chat/completions"
+api_key = ""
+"#,
+        dir = dir.display()
+    ))?;
+    let config = test_config.load()?;
+    let state = test_config.state("");
+
+    for model in ["glm-4-plus", "glm-4-plus", "glm-4-plus", "deepseek-chat"] {
+        let request = ChatRequest {
+            model: model.to_string(),
+            messages: vec![message("user", "Is sk-transcript-secret a key?")],
+            ..Default::default()
+        };
+        let ctx =
+            Dispatch::new(state.clone(), &config, model.to_string(), None).with_request(&request);
+        body(dispatch(ctx, request.messages, ChatType::Chat, None).await?).await;
+    }
+
+    // written in the background, every entry is larger than max_file_bytes,
+    // so each one rotates the last
+    let mut current = String::new();
+    for _ in 0..40 {
+        if dir.join("transcripts.1.jsonl").exists() {
+            if let Ok(contents) = std::fs::read_to_string(dir.join("transcripts.jsonl")) {
+                current = contents;
+                break;
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert_eq!(current.lines().count(), 1);
+    assert!(dir.join("transcripts.1.jsonl").exists());
+    assert!(!dir.join("transcripts.2.jsonl").exists());
+    assert!(!current.contains("sk-transcript-secret"));
+
+    let entry: Value = serde_json::from_str(current.trim())?;
+    assert_eq!(entry["model"], "glm-4-plus");
+    assert_eq!(entry["request"]["model"], "glm-4-plus");
+    assert_eq!(entry["upstream_request"]["model"], "glm-4-plus");
+    assert_eq!(entry["response"], "Beijing");
+    assert_eq!(entry["usage"]["total_tokens"], 42);
+    assert!(entry["latency_ms"].is_u64());
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use lumos::ollama::{estimate_tokens, fit_context};
+use lumos::structs::config::{Model, Tokenizer};
+use lumos::structs::ollama::ChatOptions;
+use serde_json::{json, Value};
+
+fn model(extra: &str) -> Model {
+    toml::from_str(&format!(
+        r#"
+model_name = "glm-4-plus"
+provider = "mock"
+api_key = ""
+{extra}
+"#
+    ))
+    .unwrap()
+}
+
+/// A system message, then ten turns of about 100 tokens each
+fn history() -> Vec<Value> {
+    let mut messages = vec![json!({ "role": "system", "content": "Be helpful." })];
+    for turn in 0..10 {
+        let role = if turn % 2 == 0 { "user" } else { "assistant" };
+        let content = format!("turn {} {}", turn, "word ".repeat(78));
+        messages.push(json!({ "role": role, "content": content }));
+    }
+    messages
+}
+
+fn contents(messages: &[Value]) -> Vec<String> {
+    messages
+        .iter()
+        .map(|message| {
+            let content = message["content"].as_str().unwrap();
+            content
+                .split_whitespace()
+                .take(2)
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect()
+}
+
+#[test]
+fn test_estimate_tokens() {
+    assert_eq!(estimate_tokens(Tokenizer::Generic, ""), 0);
+    assert_eq!(estimate_tokens(Tokenizer::Generic, "hello world!"), 4);
+    assert_eq!(estimate_tokens(Tokenizer::Generic, "北京是中国的首都"), 8);
+    assert_eq!(estimate_tokens(Tokenizer::DeepSeek, "北京是中国的首都"), 5);
+    assert_eq!(Tokenizer::guess("GLM-4-Plus"), Tokenizer::Glm);
+    assert_eq!(Tokenizer::guess("deepseek-chat"), Tokenizer::DeepSeek);
+    assert_eq!(Tokenizer::guess("Qwen2.5-72B-Instruct"), Tokenizer::Qwen);
+    assert_eq!(Tokenizer::guess("llama3"), Tokenizer::Generic);
+}
+
+#[test]
+fn test_fits_without_truncation() {
+    let messages = history();
+    assert_eq!(
+        fit_context(&model(""), &ChatOptions::default(), messages.clone()),
+        messages
+    );
+    let model = model("context_length = 4096");
+    assert_eq!(
+        fit_context(&model, &ChatOptions::default(), messages.clone()),
+        messages
+    );
+}
+
+#[test]
+fn test_drop_oldest_turns() {
+    let model = model("context_length = 1000\noptions = { max_tokens = 500 }");
+    let messages = fit_context(&model, &ChatOptions::default(), history());
+    // 500 tokens for the history, the system message and the last four turns,
+    // starting with a question
+    assert_eq!(
+        contents(&messages),
+        ["Be helpful.", "turn 6", "turn 7", "turn 8", "turn 9"]
+    );
+
+    // a smaller num_ctx and num_predict of the request win
+    let options = ChatOptions {
+        num_ctx: Some(600),
+        num_predict: Some(300),
+        ..Default::default()
+    };
+    let messages = fit_context(&model, &options, history());
+    assert_eq!(contents(&messages), ["Be helpful.", "turn 8", "turn 9"]);
+
+    // the last message is kept even if it doesn't fit
+    let options = ChatOptions {
+        num_ctx: Some(50),
+        ..Default::default()
+    };
+    let messages = fit_context(&model, &options, history());
+    assert_eq!(contents(&messages), ["Be helpful.", "turn 9"]);
+}
+
+#[test]
+fn test_summarize_oldest_turns() {
+    let model =
+        model("context_length = 1200\ntruncation = \"summarise\"\noptions = { max_tokens = 500 }");
+    let messages = fit_context(&model, &ChatOptions::default(), history());
+
+    assert_eq!(messages[0]["content"], "Be helpful.");
+    assert_eq!(messages[1]["role"], "system");
+    let summary = messages[1]["content"].as_str().unwrap();
+    assert!(summary.starts_with("Summary of the earlier conversation:\n- "));
+    assert!(summary.contains("- assistant: turn 5 word"));
+    assert!(summary.ends_with('…'));
+    assert_eq!(
+        contents(&messages[2..]),
+        ["turn 6", "turn 7", "turn 8", "turn 9"]
+    );
+}
+
+#[test]
+fn test_tool_results_go_with_their_call() {
+    let messages = vec![
+        json!({ "role": "user", "content": format!("turn 0 {}", "word ".repeat(78)) }),
+        json!({
+            "role": "assistant",
+            "content": "",
+            "tool_calls": [{
+                "function": { "name": "get_weather", "arguments": { "city": "Beijing" } }
+            }],
+        }),
+        json!({ "role": "tool", "content": format!("turn 2 {}", "sunny ".repeat(10)) }),
+        json!({ "role": "assistant", "content": "turn 3 It is sunny." }),
+        json!({ "role": "user", "content": format!("turn 4 {}", "word ".repeat(78)) }),
+        json!({ "role": "assistant", "content": "turn 5 Good." }),
+        json!({ "role": "user", "content": "turn 6 Thanks!" }),
+    ];
+    // the results would fit, but not without the call that asked for them
+    let model = model("context_length = 300\noptions = { max_tokens = 100 }");
+    let messages = fit_context(&model, &ChatOptions::default(), messages);
+    assert_eq!(contents(&messages), ["turn 4", "turn 5", "turn 6"]);
+}